@@ -0,0 +1,144 @@
+//! Frame rate of HID-BPF transport with one program run per packet and per frame
+//!
+//! Syscalls per frame are estimated from number of program runs, they aren't measured.
+//!
+//! Needs root and a Bloody keyboard, HID ID can be passed as argument:
+//!
+//! ```shell
+//! sudo -E cargo bench --bench bpf_batch -- 0005
+//! ```
+
+use a4keyboard::color::Color;
+use a4keyboard::devices::bloody::protocol::Command;
+use a4keyboard::devices::bloody::protocol::LEDS_COUNT;
+use a4keyboard::devices::transport::BpfTransport;
+use a4keyboard::devices::transport::ReportType;
+use a4keyboard::devices::transport::Transport;
+use a4keyboard::devices::Devices;
+use a4keyboard::Error;
+use std::time::Duration;
+use std::time::Instant;
+
+const FRAMES: u32 = 200;
+
+/// Every run of program costs two syscalls: map update and `BPF_PROG_TEST_RUN`
+const SYSCALLS_PER_RUN: usize = 2;
+
+/// Keyboard controlled by software until dropped, also if benchmark fails
+struct Control {
+    transport: BpfTransport,
+    hid: u16,
+}
+
+impl Control {
+    fn gain(transport: BpfTransport, hid: u16) -> Result<Self, Error> {
+        // created first for releasing keyboard if gaining control fails halfway
+        let mut control = Self { transport, hid };
+        control.write(&Command::PrepareControl.encode())?;
+        control.write(&Command::SetControl { software: true }.encode())?;
+
+        Ok(control)
+    }
+
+    fn write(&mut self, packet: &[u8]) -> Result<(), Error> {
+        self.transport.write(self.hid, packet)
+    }
+}
+
+impl Drop for Control {
+    fn drop(&mut self) {
+        let result = self
+            .write(&Command::PrepareControl.encode())
+            .and_then(|()| self.write(&Command::SetControl { software: false }.encode()));
+        if let Err(err) = result {
+            eprintln!("can't release control: {err}");
+        }
+    }
+}
+
+fn find_hid() -> Result<Option<u16>, Error> {
+    if let Some(hid) = std::env::args().skip(1).find(|arg| !arg.starts_with('-')) {
+        let hid = u16::from_str_radix(&hid, 16).expect("HID ID is hex, like `0005`");
+        return Ok(Some(hid));
+    }
+
+    let devices = Devices::new();
+    let mut found = None;
+    devices.for_each_devices(|info| {
+        if found.is_none() && devices.driver_name(info) == Some("bloody") {
+            found = Some(info.hid());
+        }
+        Ok::<(), Error>(())
+    })?;
+
+    Ok(found)
+}
+
+fn frames() -> Vec<[[u8; 64]; 6]> {
+    (0..FRAMES)
+        .map(|i| {
+            let value = (i % 256) as u8;
+            let color = Color {
+                r: value,
+                g: !value,
+                b: 0,
+            };
+            Command::frame(&[color; LEDS_COUNT]).map(|command| command.encode())
+        })
+        .collect()
+}
+
+fn measure(
+    name: &str,
+    runs_per_frame: usize,
+    mut send: impl FnMut(&[[u8; 64]; 6]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let frames = frames();
+    let start = Instant::now();
+    for frame in &frames {
+        send(frame)?;
+    }
+    let per_frame = start.elapsed() / FRAMES;
+
+    println!(
+        "{name:>10}: {:>8.1?} per frame, {:>6.1} frames/s, ~{} syscalls per frame (estimate)",
+        per_frame,
+        1.0 / per_frame.max(Duration::from_nanos(1)).as_secs_f64(),
+        runs_per_frame * SYSCALLS_PER_RUN,
+    );
+
+    Ok(())
+}
+
+fn main() -> Result<(), Error> {
+    let Some(hid) = find_hid()? else {
+        println!("no Bloody keyboard is found, skipping");
+        return Ok(());
+    };
+
+    let transport = match BpfTransport::new() {
+        Ok(transport) => transport,
+        Err(err) => {
+            println!("HID-BPF is unavailable ({err}), skipping");
+            return Ok(());
+        }
+    };
+
+    let mut control = Control::gain(transport, hid)?;
+
+    measure("per packet", 6, |frame| {
+        for packet in frame {
+            control.write(packet)?;
+        }
+        Ok(())
+    })?;
+
+    measure("batched", 1, |frame| {
+        let packets = frame.each_ref().map(|packet| &packet[..]);
+        control
+            .transport
+            .write_batch(hid, ReportType::Feature, &packets)
+    })?;
+
+    Ok(())
+}
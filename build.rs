@@ -3,4 +3,9 @@ fn main() {
         .source("src/bpf/write.bpf.c")
         .build_and_generate("src/bpf/write.bpf.rs")
         .unwrap();
+
+    libbpf_cargo::SkeletonBuilder::new()
+        .source("src/bpf/input.bpf.c")
+        .build_and_generate("src/bpf/input.bpf.rs")
+        .unwrap();
 }
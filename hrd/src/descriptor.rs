@@ -4,11 +4,16 @@ use crate::Report;
 #[derive(Debug, PartialEq, Eq)]
 pub struct Descriptor {
     pub main_collection: Collection,
+    /// Reports start with report ID, otherwise [`Report::report_id`] is always 1
+    pub has_report_ids: bool,
 }
 
 impl Descriptor {
-    pub(crate) fn new(main_collection: Collection) -> Self {
-        Self { main_collection }
+    pub(crate) fn new(main_collection: Collection, has_report_ids: bool) -> Self {
+        Self {
+            main_collection,
+            has_report_ids,
+        }
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &Report> {
@@ -53,6 +53,9 @@ pub enum Error {
     #[error("tag `Logical Maximum` is not set")]
     LogicalMaximumNotSet,
 
+    #[error("report is larger than 2^32 bits")]
+    ReportTooLarge,
+
     #[error("missing `End Collection` tag")]
     MissingEndCollection,
     #[error("missing pop")]
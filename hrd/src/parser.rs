@@ -3,6 +3,7 @@ use crate::Descriptor;
 use crate::Error;
 use crate::Report;
 use crate::ReportType;
+use std::collections::HashMap;
 use std::mem::size_of;
 use std::mem::swap;
 use std::mem::zeroed;
@@ -28,13 +29,30 @@ pub(crate) struct ParserCollection {
     pub state: ParserLocalState,
 }
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Default)]
 pub(crate) struct ParserLocalState {
+    /// Usage of collection, which is taken from the last `Usage` item before it
     pub usage: Option<u32>,
+    /// Items below are local to the next main item and are reset after it
+    pub usages: Vec<u32>,
     pub usage_minimum: Option<u32>,
     pub usage_maximum: Option<u32>,
 }
 
+impl ParserLocalState {
+    /// Usage of collection which starts after these items
+    fn collection_usage(&self) -> Option<u32> {
+        self.usages.last().copied().or(self.usage)
+    }
+
+    /// Local items apply only to the next main item, HID 1.11 section 6.2.2.8
+    fn reset(&mut self) {
+        self.usages.clear();
+        self.usage_minimum = None;
+        self.usage_maximum = None;
+    }
+}
+
 trait FromBytes: Copy {
     fn from_bytes(bytes: &[u8]) -> Option<Self> {
         let len = bytes.len();
@@ -82,6 +100,9 @@ const TAG_USAGE_MAXIMUM: u8 = 0x28;
 
 const TAG_EXTENDED: u8 = 0xFC;
 
+/// Report ID of descriptors which don't have `Report ID` tags
+pub(crate) const DEFAULT_REPORT_ID: u32 = 1;
+
 enum Tag {
     Long(u8),
     Short(u8),
@@ -93,6 +114,9 @@ pub struct Parser {
     global: ParserGlobalState,
     stack_collection: Vec<ParserCollection>,
     collection: ParserCollection,
+    /// Size in bits of every report which is parsed so far
+    offsets: HashMap<(ReportType, u32), u32>,
+    has_report_ids: bool,
 }
 
 impl Parser {
@@ -111,7 +135,7 @@ impl Parser {
 
         let collection = Collection::from(self.collection);
 
-        Ok(Descriptor::new(collection))
+        Ok(Descriptor::new(collection, self.has_report_ids))
     }
 
     fn inner_parse(&mut self, it: &mut dyn Iterator<Item = u8>) -> Result<(), super::Error> {
@@ -141,10 +165,11 @@ impl Parser {
                     self.global.usage_page =
                         Some(u32::from_bytes(&data).ok_or(Error::BadUsagePage)?)
                 }
-                Tag::Short(TAG_USAGE) => {
-                    self.collection.state.usage =
-                        Some(u32::from_bytes(&data).ok_or(Error::BadUsage)?)
-                }
+                Tag::Short(TAG_USAGE) => self
+                    .collection
+                    .state
+                    .usages
+                    .push(u32::from_bytes(&data).ok_or(Error::BadUsage)?),
                 Tag::Short(TAG_USAGE_MINIMUM) => {
                     self.collection.state.usage_minimum =
                         Some(u32::from_bytes(&data).ok_or(Error::BadUsageMinimum)?)
@@ -156,7 +181,12 @@ impl Parser {
                 Tag::Short(TAG_COLLECTION) => {
                     let mut collection = ParserCollection::default();
                     collection.r#type = Some(u32::from_bytes(&data).ok_or(Error::BadCollection)?);
-                    collection.state = self.collection.state.clone();
+                    // nested collection without its own usage keeps usage of parent
+                    collection.state = ParserLocalState {
+                        usage: self.collection.state.collection_usage(),
+                        ..Default::default()
+                    };
+                    self.collection.state.reset();
 
                     swap(&mut collection, &mut self.collection);
 
@@ -177,7 +207,8 @@ impl Parser {
                         Some(u32::from_bytes(&data).ok_or(Error::BadReportSize)?)
                 }
                 Tag::Short(TAG_REPORT_ID) => {
-                    self.global.report_id = Some(u32::from_bytes(&data).ok_or(Error::BadReportId)?)
+                    self.global.report_id = Some(u32::from_bytes(&data).ok_or(Error::BadReportId)?);
+                    self.has_report_ids = true;
                 }
                 Tag::Short(TAG_REPORT_COUNT) => {
                     self.global.report_count =
@@ -199,27 +230,9 @@ impl Parser {
                     self.global.physical_maximum =
                         Some(i32::from_bytes(&data).ok_or(Error::BadPhysicalMaximum)?)
                 }
-                Tag::Short(TAG_INPUT) => {
-                    self.collection.reports.push(Report::try_from_parser_states(
-                        ReportType::Input,
-                        &self.global,
-                        &self.collection.state,
-                    )?)
-                }
-                Tag::Short(TAG_OUTPUT) => {
-                    self.collection.reports.push(Report::try_from_parser_states(
-                        ReportType::Output,
-                        &self.global,
-                        &self.collection.state,
-                    )?)
-                }
-                Tag::Short(TAG_FEATURE) => {
-                    self.collection.reports.push(Report::try_from_parser_states(
-                        ReportType::Feature,
-                        &self.global,
-                        &self.collection.state,
-                    )?)
-                }
+                Tag::Short(TAG_INPUT) => self.push_report(ReportType::Input, &data)?,
+                Tag::Short(TAG_OUTPUT) => self.push_report(ReportType::Output, &data)?,
+                Tag::Short(TAG_FEATURE) => self.push_report(ReportType::Feature, &data)?,
                 Tag::Short(TAG_PUSH) => self.stack_global.push(self.global.clone()),
                 Tag::Short(TAG_POP) => {
                     self.global = self.stack_global.pop().ok_or(Error::PopWithoutPush)?
@@ -233,11 +246,37 @@ impl Parser {
 
         Ok(())
     }
+
+    /// Adds main item, its fields follow previous fields of report with the same ID
+    fn push_report(&mut self, r#type: ReportType, data: &[u8]) -> Result<(), super::Error> {
+        // empty data is the same as zero
+        let flags = u32::from_bytes(data).unwrap_or_default();
+        let report_id = self.global.report_id.unwrap_or(DEFAULT_REPORT_ID);
+        let offset = self.offsets.entry((r#type, report_id)).or_default();
+
+        let report = Report::try_from_parser_states(
+            r#type,
+            flags,
+            *offset,
+            &self.global,
+            &self.collection.state,
+        )?;
+        // sizes are taken from device, so they can be anything
+        *offset = report
+            .report_size
+            .checked_mul(report.report_count)
+            .and_then(|size| offset.checked_add(size))
+            .ok_or(Error::ReportTooLarge)?;
+
+        self.collection.state.reset();
+        self.collection.reports.push(report);
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{Parser, Report, ReportType};
+    use crate::{Error, Parser, Report, ReportType};
 
     const REPORT_DESCRIPTOR1: &[u8] = &[
         0x05, 0x01, 0x09, 0x06, 0xa1, 0x01, 0x85, 0x01, 0x05, 0x07, 0x19, 0xe0, 0x29, 0xe7, 0x15,
@@ -255,6 +294,7 @@ mod test {
     fn test1() {
         let descriptor = Parser::default().parse(REPORT_DESCRIPTOR1).unwrap();
 
+        assert!(descriptor.has_report_ids);
         assert_eq!(descriptor.main_collection.reports.len(), 0);
         assert_eq!(descriptor.main_collection.nested.len(), 4);
 
@@ -278,6 +318,9 @@ mod test {
                 logical_maximum: 1,
                 physical_minimum: None,
                 physical_maximum: None,
+                usages: Vec::new(),
+                flags: 0x02,
+                bit_offset: 0,
             }
         );
         assert_eq!(
@@ -295,6 +338,9 @@ mod test {
                 logical_maximum: 1,
                 physical_minimum: None,
                 physical_maximum: None,
+                usages: Vec::new(),
+                flags: 0x02,
+                bit_offset: 8,
             }
         );
 
@@ -316,6 +362,9 @@ mod test {
                 logical_maximum: 183,
                 physical_minimum: None,
                 physical_maximum: None,
+                usages: Vec::new(),
+                flags: 0x00,
+                bit_offset: 0,
             }
         );
 
@@ -337,6 +386,9 @@ mod test {
                 logical_maximum: 0x023C,
                 physical_minimum: None,
                 physical_maximum: None,
+                usages: Vec::new(),
+                flags: 0x00,
+                bit_offset: 0,
             }
         );
 
@@ -358,6 +410,9 @@ mod test {
                 logical_maximum: 0xFF,
                 physical_minimum: None,
                 physical_maximum: None,
+                usages: Vec::new(),
+                flags: 0x00,
+                bit_offset: 0,
             }
         );
         assert_eq!(
@@ -375,7 +430,92 @@ mod test {
                 logical_maximum: 0xFF,
                 physical_minimum: None,
                 physical_maximum: None,
+                usages: Vec::new(),
+                flags: 0x02,
+                bit_offset: 0,
             }
         );
     }
+
+    /// Boot keyboard from HID specification, without report IDs
+    const BOOT_KEYBOARD: &[u8] = &[
+        0x05, 0x01, 0x09, 0x06, 0xa1, 0x01, 0x05, 0x07, 0x19, 0xe0, 0x29, 0xe7, 0x15, 0x00, 0x25,
+        0x01, 0x75, 0x01, 0x95, 0x08, 0x81, 0x02, 0x95, 0x01, 0x75, 0x08, 0x81, 0x01, 0x95, 0x06,
+        0x75, 0x08, 0x15, 0x00, 0x25, 0x65, 0x05, 0x07, 0x19, 0x00, 0x29, 0x65, 0x81, 0x00, 0xc0,
+    ];
+
+    #[test]
+    fn offsets() {
+        let descriptor = Parser::default().parse(BOOT_KEYBOARD).unwrap();
+        assert!(!descriptor.has_report_ids);
+
+        let fields = Vec::from_iter(descriptor.iter().map(|report| {
+            (
+                report.bit_offset,
+                report.report_size * report.report_count,
+                report.is_constant(),
+                report.is_variable(),
+            )
+        }));
+        assert_eq!(
+            fields,
+            [
+                (0, 8, false, true),
+                (8, 8, true, false),
+                (16, 48, false, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn local_items() {
+        // two buttons with their own usages followed by padding
+        let descriptor = Parser::default()
+            .parse(&[
+                0x05, 0x0c, 0x09, 0x01, 0xa1, 0x01, 0x09, 0xe9, 0x09, 0xea, 0x15, 0x00, 0x25, 0x01,
+                0x75, 0x01, 0x95, 0x02, 0x81, 0x02, 0x19, 0xb5, 0x29, 0xb6, 0x95, 0x02, 0x81, 0x02,
+                0x95, 0x04, 0x81, 0x01, 0xc0,
+            ])
+            .unwrap();
+
+        let fields = Vec::from_iter(descriptor.iter().map(|report| {
+            (
+                report.usage,
+                report.usages.as_slice(),
+                report.usage_minimum,
+                report.usage_maximum,
+            )
+        }));
+        assert_eq!(
+            fields,
+            [
+                (0x01, [0xe9, 0xea].as_slice(), None, None),
+                (0x01, [].as_slice(), Some(0xb5), Some(0xb6)),
+                (0x01, [].as_slice(), None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn report_too_large() {
+        // `Report Size` and `Report Count` are 0x10000
+        let descriptor = [
+            0x05, 0x01, 0x09, 0x06, 0xa1, 0x01, 0x15, 0x00, 0x25, 0x01, 0x77, 0x00, 0x00, 0x01,
+            0x00, 0x97, 0x00, 0x00, 0x01, 0x00, 0x81, 0x02, 0xc0,
+        ];
+        assert!(matches!(
+            Parser::default().parse(&descriptor),
+            Err(Error::ReportTooLarge)
+        ));
+
+        // the second field doesn't fit after the first one
+        let descriptor = [
+            0x05, 0x01, 0x09, 0x06, 0xa1, 0x01, 0x15, 0x00, 0x25, 0x01, 0x77, 0x00, 0x00, 0x00,
+            0x80, 0x95, 0x01, 0x81, 0x02, 0x81, 0x02, 0xc0,
+        ];
+        assert!(matches!(
+            Parser::default().parse(&descriptor),
+            Err(Error::ReportTooLarge)
+        ));
+    }
 }
@@ -1,9 +1,10 @@
 use crate::parser::ParserCollection;
 use crate::parser::ParserGlobalState;
 use crate::parser::ParserLocalState;
+use crate::parser::DEFAULT_REPORT_ID;
 use crate::Error;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ReportType {
     Input,
     Output,
@@ -14,7 +15,10 @@ pub enum ReportType {
 pub struct Report {
     pub r#type: ReportType,
     pub usage_page: u32,
+    /// Usage of collection which contains report
     pub usage: u32,
+    /// Usages of fields in order, set by `Usage` items before main item
+    pub usages: Vec<u32>,
     pub usage_minimum: Option<u32>,
     pub usage_maximum: Option<u32>,
     pub report_size: u32,
@@ -24,6 +28,10 @@ pub struct Report {
     pub logical_maximum: i32,
     pub physical_minimum: Option<i32>,
     pub physical_maximum: Option<i32>,
+    /// Data of main item, see [`Report::is_constant`] and [`Report::is_variable`]
+    pub flags: u32,
+    /// Position of the first bit in report, after report ID
+    pub bit_offset: u32,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -37,30 +45,53 @@ impl Collection {
         Box::new(
             self.reports
                 .iter()
-                .chain(self.nested.iter().map(Collection::iter).flatten()),
+                .chain(self.nested.iter().flat_map(Collection::iter)),
         )
     }
 }
 
 impl Report {
+    pub const FLAG_CONSTANT: u32 = 0x01;
+    pub const FLAG_VARIABLE: u32 = 0x02;
+
+    /// Padding or fixed data which doesn't carry values
+    pub fn is_constant(&self) -> bool {
+        self.flags & Self::FLAG_CONSTANT != 0
+    }
+
+    /// Every field has its own usage, like bitmap of keys. Otherwise fields are array of
+    /// usage indices, like currently pressed keys.
+    pub fn is_variable(&self) -> bool {
+        self.flags & Self::FLAG_VARIABLE != 0
+    }
+
     pub(crate) fn try_from_parser_states(
         r#type: ReportType,
+        flags: u32,
+        bit_offset: u32,
         global: &ParserGlobalState,
         local: &ParserLocalState,
     ) -> Result<Self, Error> {
         Ok(Report {
             r#type,
             usage_page: global.usage_page.ok_or(Error::UsagePageNotSet)?,
-            usage: local.usage.ok_or(Error::UsageNotSet)?,
+            // report outside of collections uses its own usage
+            usage: local
+                .usage
+                .or_else(|| local.usages.last().copied())
+                .ok_or(Error::UsageNotSet)?,
+            usages: local.usages.clone(),
             usage_minimum: local.usage_minimum,
             usage_maximum: local.usage_maximum,
             report_size: global.report_size.ok_or(Error::ReportSizeNotSet)?,
-            report_id: global.report_id.unwrap_or(1),
+            report_id: global.report_id.unwrap_or(DEFAULT_REPORT_ID),
             report_count: global.report_count.ok_or(Error::ReportCountNotSet)?,
             logical_minimum: global.logical_minimum.ok_or(Error::LogicalMinimumNotSet)?,
             logical_maximum: global.logical_maximum.ok_or(Error::LogicalMaximumNotSet)?,
             physical_minimum: global.physical_minimum,
             physical_maximum: global.physical_maximum,
+            flags,
+            bit_offset,
         })
     }
 }
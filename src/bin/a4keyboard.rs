@@ -1,9 +1,35 @@
 use a4keyboard::color::Color;
-use a4keyboard::devices::DeviceHandle;
+use a4keyboard::devices::definition::DEFAULT_DEFINITIONS_DIR;
+use a4keyboard::devices::selector::DeviceSelector;
+use a4keyboard::devices::transport::bpf::DEFAULT_PIN_DIR;
+use a4keyboard::devices::transport::capture;
+use a4keyboard::devices::transport::BpfTransport;
+use a4keyboard::devices::transport::CaptureTransport;
+use a4keyboard::devices::transport::TransportKind;
 use a4keyboard::devices::Devices;
+use a4keyboard::devices::DEFAULT_SYSFS_ROOT;
+use a4keyboard::layout;
+use a4keyboard::layout::Layout;
+use a4keyboard::usbmon::Address;
+use a4keyboard::usbmon::UsbId;
+use a4keyboard::Error;
+use cmd::keys::KeyAssignment;
+use signal_hook::consts::SIGINT;
+use signal_hook::consts::SIGTERM;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 
 mod cmd {
+    pub mod calibrate;
     pub mod color;
+    pub mod doctor;
+    pub mod events;
+    pub mod keys;
+    pub mod list;
+    pub mod replay;
+    pub mod usbmon;
 
     #[cfg(feature = "disco")]
     pub mod disco;
@@ -17,9 +43,157 @@ enum Command {
         color: Color,
     },
 
+    /// Set colors of separate keys
+    Keys {
+        /// Key names, comma separated groups and ranges with colors, like `esc=f00`,
+        /// `w,a,s,d=00f` or `f1-f12=333`
+        #[arg(value_name = "KEYS=HEXCOLOR", required = true)]
+        assignments: Vec<KeyAssignment>,
+
+        /// Color of keys which are not assigned
+        #[arg(long, value_name = "HEXCOLOR", default_value = "000")]
+        base: Color,
+    },
+
     /// Enter "disco" mode
     #[cfg(feature = "disco")]
     Disco {},
+
+    /// Find out which key is at each index by lighting keys one by one
+    Calibrate {
+        /// Path where layout file is written
+        #[arg(long, short, value_name = "FILE")]
+        out: PathBuf,
+
+        /// Indices which have no LED, they aren't lit and aren't written to layout file
+        #[arg(
+            long,
+            visible_alias = "skip",
+            value_name = "INDEX",
+            value_delimiter = ','
+        )]
+        none: Vec<usize>,
+
+        /// Event devices to read key presses from (default: all input devices of keyboard)
+        #[arg(long, value_name = "PATH")]
+        input: Vec<PathBuf>,
+    },
+
+    /// Print key presses and releases of keyboards, read through HID-BPF (Linux 6.11+)
+    Events {},
+
+    /// Return control of devices to firmware (e.g. after crash)
+    Release {},
+
+    /// Run command and write every sent report to capture file
+    Record {
+        /// Path where capture is written
+        #[arg(long, short, value_name = "FILE")]
+        out: PathBuf,
+
+        /// Command with its arguments, like `disco` or `color f00`
+        #[arg(
+            value_name = "COMMAND",
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true
+        )]
+        command: Vec<String>,
+    },
+
+    /// Send reports from capture file with original timing, reports which were sent
+    /// together, like frames of `disco`, are sent as burst
+    Replay {
+        /// Capture written by `record`
+        #[arg(value_name = "FILE")]
+        capture: PathBuf,
+
+        /// Send all reports to this HID ID instead of recorded ones
+        #[arg(long, value_name = "HID", value_parser = parse_hid)]
+        hid: Option<u16>,
+    },
+
+    /// Decode SET_REPORT requests from usbmon text output or pcap file
+    Usbmon {
+        /// Capture of `/sys/kernel/debug/usb/usbmon/Nu` or pcap file from Wireshark
+        #[arg(value_name = "FILE")]
+        capture: PathBuf,
+
+        /// Show only requests to device with this `VID:PID`, capture must contain its
+        /// enumeration
+        #[arg(long, value_name = "VID:PID")]
+        id: Option<UsbId>,
+
+        /// Show only requests to device with this `BUS:DEVICE` address
+        #[arg(long, value_name = "BUS:DEVICE")]
+        address: Option<Address>,
+    },
+
+    /// Load HID-BPF program once and pin it in bpffs, later runs use it without `CAP_BPF`
+    Pin {
+        /// Directory in bpffs for the program and its map
+        #[arg(long, value_name = "DIR", default_value = DEFAULT_PIN_DIR)]
+        dir: PathBuf,
+    },
+
+    /// Remove HID-BPF program pinned by `pin`
+    Unpin {
+        #[arg(long, value_name = "DIR", default_value = DEFAULT_PIN_DIR)]
+        dir: PathBuf,
+    },
+
+    /// Check kernel features, permissions and devices, and suggest fixes
+    Doctor {},
+
+    /// Show detected HID devices and drivers which support them
+    List {
+        /// Print devices as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+impl Command {
+    fn needs_control(&self) -> bool {
+        // capture contains its own control reports
+        !matches!(
+            self,
+            Command::List { .. }
+                | Command::Release {}
+                | Command::Replay { .. }
+                | Command::Usbmon { .. }
+                | Command::Pin { .. }
+                | Command::Unpin { .. }
+                | Command::Doctor {}
+                | Command::Events {}
+        )
+    }
+
+    /// Commands which send reports through devices, others have their own transport or
+    /// don't send anything
+    fn can_be_recorded(&self) -> bool {
+        !matches!(
+            self,
+            Command::Record { .. }
+                | Command::Replay { .. }
+                | Command::Usbmon { .. }
+                | Command::Pin { .. }
+                | Command::Unpin { .. }
+                | Command::Doctor {}
+        )
+    }
+}
+
+fn not_recordable() -> Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "only commands which control devices can be recorded",
+    )
+    .into()
+}
+
+fn parse_hid(s: &str) -> Result<u16, String> {
+    u16::from_str_radix(s, 16).map_err(|err| format!("{err} `{s}`, expected hex like `0005`"))
 }
 
 #[derive(clap::Parser)]
@@ -29,28 +203,190 @@ struct Args {
 
     #[arg(long)]
     no_gain_control: bool,
+
+    /// Leave devices controlled by software on exit, so colors set by `color` or `keys`
+    /// stay until `release`, otherwise firmware effects come back
+    #[arg(long, conflicts_with = "no_gain_control")]
+    keep: bool,
+
+    /// Backend for sending reports to devices
+    #[arg(long, value_enum, default_value_t)]
+    transport: TransportKind,
+
+    /// Print reports instead of sending them, the same as `--transport dry-run`
+    #[arg(long, conflicts_with = "transport")]
+    dry_run: bool,
+
+    /// Path where sysfs is mounted
+    #[arg(long, env = "A4KEYBOARD_SYSFS_ROOT", default_value = DEFAULT_SYSFS_ROOT)]
+    sysfs_root: PathBuf,
+
+    /// Directory with TOML device definitions which are used in addition to built-in drivers
+    #[arg(long, env = "A4KEYBOARD_DEFINITIONS", default_value = DEFAULT_DEFINITIONS_DIR)]
+    definitions: PathBuf,
+
+    /// Apply command only to matched devices (`hid:0005`, `id:09da:fa10`,
+    /// `sysfs:0003:09DA:FA10.0005` or `uniq:SERIAL`), can be repeated
+    #[arg(short, long = "device", value_name = "SELECTOR")]
+    devices: Vec<DeviceSelector>,
+
+    /// Physical layout of keyboard
+    #[arg(long, value_enum, default_value_t)]
+    layout: layout::Variant,
+
+    /// Layout file written by `calibrate` which is used instead of built-in layout
+    #[arg(long, value_name = "FILE")]
+    layout_file: Option<PathBuf>,
 }
 
 fn main() {
     env_logger::init();
 
+    if let Err(err) = run(clap::Parser::parse()) {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<(), Error> {
     let Args {
         command,
         no_gain_control,
-    } = clap::Parser::parse();
+        keep,
+        transport,
+        dry_run,
+        sysfs_root,
+        definitions,
+        devices: selectors,
+        layout: variant,
+        layout_file,
+    } = args;
+
+    // commands which don't need devices
+    let command = match command {
+        Command::Usbmon {
+            capture,
+            id,
+            address,
+        } => return cmd::usbmon::run(&capture, id, address),
+        Command::Pin { dir } => return BpfTransport::pin(dir).map(drop),
+        Command::Unpin { dir } => return BpfTransport::unpin(dir),
+        command => command,
+    };
+
+    let transport = if dry_run {
+        TransportKind::DryRun
+    } else {
+        transport
+    };
 
-    if !no_gain_control {
-        Devices::for_each_supported_devices(DeviceHandle::gain_control).unwrap();
+    let mut devices = Devices::new();
+    match devices.load_definitions(&definitions) {
+        // doctor reports malformed definitions as failed check
+        Err(_) if matches!(command, Command::Doctor {}) => {}
+        result => {
+            result?;
+        }
     }
+    devices.set_transport(transport);
+    devices.set_sysfs_root(&sysfs_root);
+    devices.set_selectors(selectors);
 
-    match command {
-        Command::Color { color } => {
-            cmd::color::run(color).unwrap();
+    let terminated = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        // second signal terminates process immediately
+        signal_hook::flag::register_conditional_shutdown(signal, 1, Arc::clone(&terminated))?;
+        signal_hook::flag::register(signal, Arc::clone(&terminated))?;
+    }
+
+    let command = match command {
+        Command::Record { out, command } => {
+            // the same as nested subcommand, which clap can't derive for recursive enum
+            let command: Command = match clap::Parser::try_parse_from(
+                std::iter::once("a4keyboard record".to_owned()).chain(command),
+            ) {
+                Ok(command) => command,
+                Err(err) if !err.use_stderr() => {
+                    // `--help` of nested command
+                    err.print()?;
+                    return Ok(());
+                }
+                Err(err) => {
+                    let message = err.to_string();
+                    let message = message.trim_start_matches("error: ").trim_end();
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+                }
+            };
+            if !command.can_be_recorded() {
+                return Err(not_recordable());
+            }
+
+            let inner = transport.open(&sysfs_root)?;
+            devices.use_transport(Box::new(CaptureTransport::create(inner, out)?));
+            command
+        }
+        Command::Doctor {} => return cmd::doctor::run(&devices, &sysfs_root, &definitions),
+        Command::Replay { capture, hid } => {
+            let records = capture::load_capture(capture)?;
+            let mut transport = transport.open(&sysfs_root)?;
+            return cmd::replay::run(&mut devices, transport.as_mut(), &records, hid, &terminated);
         }
+        command => command,
+    };
+
+    // only commands which use layout file load it, so broken file doesn't block others
+    let layout = match layout_file {
+        Some(path) if matches!(command, Command::Keys { .. } | Command::Calibrate { .. }) => {
+            Layout::load(&path)
+                .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))?
+        }
+        _ => Layout::new(variant),
+    };
+
+    if no_gain_control || !command.needs_control() {
+        return run_command(&mut devices, command, &layout, &terminated);
+    }
+
+    let mut control = devices.gain_control()?;
+    run_command(&mut control, command, &layout, &terminated)?;
+
+    if keep {
+        control.keep();
+    }
+
+    Ok(())
+}
+
+fn run_command(
+    devices: &mut Devices,
+    command: Command,
+    layout: &Layout,
+    terminated: &AtomicBool,
+) -> Result<(), Error> {
+    match command {
+        Command::Color { color } => cmd::color::run(devices, color),
+
+        Command::Keys { assignments, base } => cmd::keys::run(devices, layout, &assignments, base),
 
         #[cfg(feature = "disco")]
-        Command::Disco {} => {
-            cmd::disco::run().unwrap();
+        Command::Disco {} => cmd::disco::run(devices, terminated),
+
+        Command::Calibrate { out, none, input } => {
+            cmd::calibrate::run(devices, layout.variant(), &none, input, &out, terminated)
         }
+
+        Command::Events {} => cmd::events::run(devices, layout.variant(), terminated),
+
+        Command::Release {} => devices.for_each_supported_devices(|dev| dev.release_control()),
+
+        Command::List { json } => cmd::list::run(devices, json),
+
+        Command::Record { .. }
+        | Command::Replay { .. }
+        | Command::Usbmon { .. }
+        | Command::Pin { .. }
+        | Command::Unpin { .. }
+        // nested commands of `record` which are rejected there
+        | Command::Doctor {} => Err(not_recordable()),
     }
 }
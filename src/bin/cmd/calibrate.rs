@@ -0,0 +1,259 @@
+use a4keyboard::color::Color;
+use a4keyboard::devices::Devices;
+use a4keyboard::layout;
+use a4keyboard::layout::Layout;
+use a4keyboard::layout::KEYS_COUNT;
+use a4keyboard::Error;
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read as _;
+use std::io::Write as _;
+use std::mem::size_of;
+use std::os::fd::AsRawFd as _;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+const EV_KEY: u16 = 0x01;
+const KEY_PRESSED: i32 = 1;
+
+/// Linux key codes (`KEY_*` from `linux/input-event-codes.h`) and usage IDs on
+/// Keyboard/Keypad page, same as `hid_keyboard` table in `drivers/hid/hid-input.c`
+#[rustfmt::skip]
+const KEY_CODES: &[(u16, u16)] = &[
+    (1, 0x29), (2, 0x1E), (3, 0x1F), (4, 0x20), (5, 0x21), (6, 0x22), (7, 0x23), (8, 0x24),
+    (9, 0x25), (10, 0x26), (11, 0x27), (12, 0x2D), (13, 0x2E), (14, 0x2A), (15, 0x2B),
+    (16, 0x14), (17, 0x1A), (18, 0x08), (19, 0x15), (20, 0x17), (21, 0x1C), (22, 0x18),
+    (23, 0x0C), (24, 0x12), (25, 0x13), (26, 0x2F), (27, 0x30), (28, 0x28), (29, 0xE0),
+    (30, 0x04), (31, 0x16), (32, 0x07), (33, 0x09), (34, 0x0A), (35, 0x0B), (36, 0x0D),
+    (37, 0x0E), (38, 0x0F), (39, 0x33), (40, 0x34), (41, 0x35), (42, 0xE1), (43, 0x31),
+    (44, 0x1D), (45, 0x1B), (46, 0x06), (47, 0x19), (48, 0x05), (49, 0x11), (50, 0x10),
+    (51, 0x36), (52, 0x37), (53, 0x38), (54, 0xE5), (55, 0x55), (56, 0xE2), (57, 0x2C),
+    (58, 0x39), (59, 0x3A), (60, 0x3B), (61, 0x3C), (62, 0x3D), (63, 0x3E), (64, 0x3F),
+    (65, 0x40), (66, 0x41), (67, 0x42), (68, 0x43), (69, 0x53), (70, 0x47), (71, 0x5F),
+    (72, 0x60), (73, 0x61), (74, 0x56), (75, 0x5C), (76, 0x5D), (77, 0x5E), (78, 0x57),
+    (79, 0x59), (80, 0x5A), (81, 0x5B), (82, 0x62), (83, 0x63), (86, 0x64), (87, 0x44),
+    (88, 0x45), (96, 0x58), (97, 0xE4), (98, 0x54), (99, 0x46), (100, 0xE6), (102, 0x4A),
+    (103, 0x52), (104, 0x4B), (105, 0x50), (106, 0x4F), (107, 0x4D), (108, 0x51), (109, 0x4E),
+    (110, 0x49), (111, 0x4C), (119, 0x48), (125, 0xE3), (126, 0xE7), (127, 0x65),
+];
+
+/// Usage ID of `nonushash` which is reported by kernel as `KEY_BACKSLASH`
+const USAGE_NON_US_HASH: u16 = 0x32;
+const USAGE_BACKSLASH: u16 = 0x31;
+
+fn usage_of(code: u16, variant: layout::Variant) -> Option<u16> {
+    let &(_, usage) = KEY_CODES.iter().find(|&&(c, _)| c == code)?;
+
+    match (variant, usage) {
+        (layout::Variant::Iso, USAGE_BACKSLASH) => Some(USAGE_NON_US_HASH),
+        _ => Some(usage),
+    }
+}
+
+/// Returns `/dev/input/eventN` nodes of all input devices which are created for HID device
+fn event_nodes(sysfs_path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut nodes = Vec::new();
+
+    let Ok(inputs) = fs::read_dir(sysfs_path.join("input")) else {
+        return Ok(nodes);
+    };
+
+    for input in inputs {
+        for entry in fs::read_dir(input?.path())? {
+            let name = entry?.file_name();
+            if name.as_encoded_bytes().starts_with(b"event") {
+                nodes.push(Path::new("/dev/input").join(name));
+            }
+        }
+    }
+
+    Ok(nodes)
+}
+
+/// Looks up event devices of all interfaces of supported keyboards
+fn find_event_nodes(devices: &Devices) -> Result<Vec<PathBuf>, Error> {
+    let mut ids = HashSet::new();
+    devices.for_each_devices(|info| {
+        if devices.driver_name(info).is_some() {
+            ids.insert((info.vid(), info.pid()));
+        }
+        Ok::<(), Error>(())
+    })?;
+
+    let mut nodes = Vec::new();
+    devices.for_each_devices(|info| {
+        if ids.contains(&(info.vid(), info.pid())) {
+            nodes.extend(event_nodes(info.sysfs_path())?);
+        }
+        Ok::<(), Error>(())
+    })?;
+
+    Ok(nodes)
+}
+
+/// Waits for key press on any of `inputs` and returns its key code,
+/// `None` if process is terminated
+fn read_key_press(inputs: &mut [File], terminated: &AtomicBool) -> io::Result<Option<u16>> {
+    let mut fds = Vec::from_iter(inputs.iter().map(|input| libc::pollfd {
+        fd: input.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    }));
+
+    loop {
+        // SAFETY: `fds` is valid array of `pollfd` with correct length
+        let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, -1) };
+        if terminated.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+
+        for (input, fd) in inputs.iter_mut().zip(&fds) {
+            if fd.revents & libc::POLLIN == 0 {
+                continue;
+            }
+
+            let mut buf = [0u8; size_of::<libc::input_event>()];
+            input.read_exact(&mut buf)?;
+
+            // SAFETY: `input_event` is plain C structure and buffer has the same size
+            let event: libc::input_event = unsafe { std::ptr::read_unaligned(buf.as_ptr().cast()) };
+            if event.type_ == EV_KEY && event.value == KEY_PRESSED {
+                return Ok(Some(event.code));
+            }
+        }
+    }
+}
+
+pub fn run(
+    devices: &mut Devices,
+    variant: layout::Variant,
+    none: &[usize],
+    inputs: Vec<PathBuf>,
+    out: &Path,
+    terminated: &AtomicBool,
+) -> Result<(), Error> {
+    let inputs = if inputs.is_empty() {
+        find_event_nodes(devices)?
+    } else {
+        inputs
+    };
+    if inputs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "input devices of keyboard are not found, use `--input`",
+        )
+        .into());
+    }
+
+    let mut inputs = inputs
+        .iter()
+        .map(|path| match File::open(path) {
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+                Err(Error::PermissionDenied {
+                    what: path.display().to_string(),
+                    source: err.into(),
+                })
+            }
+            result => Ok(result?),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let result = calibrate(devices, variant, &mut inputs, none, out, terminated);
+
+    // drop key presses which are buffered by terminal during calibration
+    // SAFETY: just a syscall
+    unsafe { libc::tcflush(libc::STDIN_FILENO, libc::TCIFLUSH) };
+
+    result
+}
+
+fn calibrate(
+    devices: &mut Devices,
+    variant: layout::Variant,
+    inputs: &mut [File],
+    none: &[usize],
+    out: &Path,
+    terminated: &AtomicBool,
+) -> Result<(), Error> {
+    let builtin = Layout::new(variant);
+
+    let mut usages = [None; KEYS_COUNT];
+    let mut firmware_keys = Vec::new();
+    let white = Color {
+        r: 0xff,
+        g: 0xff,
+        b: 0xff,
+    };
+
+    for index in 0..KEYS_COUNT {
+        if none.contains(&index) {
+            continue;
+        }
+
+        let hint = builtin.key(index).map_or("-", |key| key.name);
+
+        // keys like `fn` are handled by firmware and never reach input devices
+        if let Some(key) = builtin.key(index).filter(|key| key.usage.is_none()) {
+            println!(
+                "[{index:3}/{KEYS_COUNT}] `{hint}` has no usage ID, kept from built-in layout"
+            );
+            firmware_keys.push((index, *key));
+            continue;
+        }
+
+        let mut colors = [Color::default(); KEYS_COUNT];
+        colors[index] = white;
+        devices.for_each_supported_devices(|dev| dev.set_colors(&colors))?;
+
+        print!("[{index:3}/{KEYS_COUNT}] press the lit key (expected `{hint}`): ");
+        io::stdout().flush()?;
+
+        let usage = loop {
+            let Some(code) = read_key_press(inputs, terminated)? else {
+                println!();
+                println!("calibration is interrupted");
+                return Ok(());
+            };
+            let Some(usage) =
+                usage_of(code, variant).filter(|&u| variant.key_by_usage(u).is_some())
+            else {
+                print!("key code {code} is not in layout, try again: ");
+                io::stdout().flush()?;
+                continue;
+            };
+
+            if let Some(other) = usages.iter().position(|u| *u == Some(usage)) {
+                print!("key is already assigned to {other}, try again: ");
+                io::stdout().flush()?;
+                continue;
+            }
+
+            break usage;
+        };
+
+        let name = variant.key_by_usage(usage).map_or("?", |key| key.name);
+        println!("{name}");
+
+        usages[index] = Some(usage);
+    }
+
+    let mut layout = Layout::from_usages(variant, &usages);
+    for (index, key) in firmware_keys {
+        layout.set_key(index, Some(key));
+    }
+    layout.save(out)?;
+    println!("layout is written to {}", out.display());
+
+    Ok(())
+}
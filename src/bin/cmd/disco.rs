@@ -3,6 +3,8 @@ use a4keyboard::devices::Devices;
 use a4keyboard::utils::AsBytes as _;
 use a4keyboard::Error;
 use rand::RngCore as _;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 
 fn make_diff(color: Color, diff: Color, speed: u8) -> Color {
     fn make_diff(color: u8, diff: u8, speed: u8) -> u8 {
@@ -24,7 +26,7 @@ fn make_diff(color: Color, diff: Color, speed: u8) -> Color {
     }
 }
 
-pub fn run() -> Result<(), Error> {
+pub fn run(devices: &mut Devices, terminated: &AtomicBool) -> Result<(), Error> {
     let mut values = [Color::default(); 104];
 
     let mut rng = rand::thread_rng();
@@ -35,7 +37,7 @@ pub fn run() -> Result<(), Error> {
 
     let mut accels = [Color::default(); 104];
 
-    loop {
+    while !terminated.load(Ordering::Relaxed) {
         rng.fill_bytes(accels.as_bytes_mut());
 
         values
@@ -48,6 +50,8 @@ pub fn run() -> Result<(), Error> {
             .zip(accels.iter().copied())
             .for_each(|(speed, accel)| *speed = make_diff(*speed, accel, 2));
 
-        Devices::for_each_supported_devices(|dev| dev.set_colors(&values))?;
+        devices.for_each_supported_devices(|dev| dev.set_colors(&values))?;
     }
+
+    Ok(())
 }
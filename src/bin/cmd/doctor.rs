@@ -0,0 +1,30 @@
+use a4keyboard::devices::Devices;
+use a4keyboard::doctor::Probe;
+use a4keyboard::doctor::Status;
+use a4keyboard::Error;
+use std::io;
+use std::path::Path;
+
+pub fn run(devices: &Devices, sysfs_root: &Path, definitions: &Path) -> Result<(), Error> {
+    let mut probe = Probe::new();
+    probe.set_sysfs_root(sysfs_root);
+    probe.set_definitions_dir(definitions);
+
+    let checks = probe.run(devices);
+    for check in &checks {
+        println!("[{:^4}] {}: {}", check.status, check.name, check.message);
+        if let Some(fix) = &check.fix {
+            println!("       fix: {fix}");
+        }
+    }
+
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == Status::Fail)
+        .count();
+    if failed > 0 {
+        return Err(io::Error::other(format!("{failed} checks failed")).into());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,93 @@
+use a4keyboard::devices::input::BpfInput;
+use a4keyboard::devices::input::KeyDecoder;
+use a4keyboard::devices::input::KeyEvent;
+use a4keyboard::devices::DeviceInfo;
+use a4keyboard::devices::Devices;
+use a4keyboard::layout;
+use a4keyboard::Error;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+/// How often termination is checked while no keys are pressed
+const POLL_TIMEOUT: Duration = Duration::from_millis(200);
+
+const USAGE_PAGE_KEYBOARD: u16 = 0x07;
+
+fn key_name(event: &KeyEvent, variant: layout::Variant) -> String {
+    let key = (event.usage_page == USAGE_PAGE_KEYBOARD)
+        .then(|| variant.key_by_usage(event.usage))
+        .flatten();
+
+    match key {
+        Some(key) => key.name.to_owned(),
+        None => format!("{:02x}:{:04x}", event.usage_page, event.usage),
+    }
+}
+
+/// Returns sysfs directory of USB device which has the interface of HID device, or
+/// directory of HID device itself if it isn't connected by USB
+fn parent_device(info: &DeviceInfo) -> PathBuf {
+    // bus/hid/devices/* links to devices/.../<usb device>/<interface>/<hid device>
+    let path = fs::canonicalize(info.sysfs_path()).unwrap_or_else(|_| info.sysfs_path().into());
+    let usb = path
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.join("idVendor").exists());
+
+    usb.unwrap_or(&path).to_owned()
+}
+
+pub fn run(
+    devices: &Devices,
+    variant: layout::Variant,
+    terminated: &AtomicBool,
+) -> Result<(), Error> {
+    // keys are often reported by another interface of keyboard than the one with lighting
+    let mut keyboards = Vec::new();
+    devices.for_each_devices(|info| {
+        if devices.driver_name(info).is_some() {
+            keyboards.push(parent_device(info));
+        }
+        Ok::<(), Error>(())
+    })?;
+
+    let mut decoders = BTreeMap::new();
+    devices.for_each_devices(|info| {
+        let keyboard = parent_device(info);
+        let decoder = KeyDecoder::new(info.report_descriptor());
+        if keyboards.contains(&keyboard) && decoder.has_keys() {
+            decoders.insert(info.hid(), decoder);
+        }
+        Ok::<(), Error>(())
+    })?;
+
+    if decoders.is_empty() {
+        return Err(Error::NoSupportedDevices);
+    }
+
+    let hids = Vec::from_iter(decoders.keys().copied());
+    let mut input = BpfInput::attach(&hids)?;
+
+    while !terminated.load(Ordering::Relaxed) {
+        for report in input.read(POLL_TIMEOUT)? {
+            let Some(decoder) = decoders.get_mut(&report.hid) else {
+                continue;
+            };
+
+            for event in decoder.decode(&report.data) {
+                let action = if event.pressed { "press" } else { "release" };
+                println!(
+                    "HID {:04X}: {action} {}",
+                    report.hid,
+                    key_name(&event, variant)
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use a4keyboard::color::Color;
+use a4keyboard::devices::Devices;
+use a4keyboard::layout::Layout;
+use a4keyboard::layout::KEYS_COUNT;
+use a4keyboard::Error;
+use std::io;
+use std::str::FromStr;
+
+/// Assignment of color to group of keys, like `w,a,s,d=00f` or `f1-f12=333`
+#[derive(Debug, Clone)]
+pub struct KeyAssignment {
+    keys: String,
+    color: Color,
+}
+
+impl FromStr for KeyAssignment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (keys, color) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("incorrect assignment `{s}`, expected `KEYS=HEXCOLOR`"))?;
+
+        let color = color.parse().map_err(|err| format!("{err} `{color}`"))?;
+
+        Ok(Self {
+            keys: keys.to_owned(),
+            color,
+        })
+    }
+}
+
+pub fn run(
+    devices: &mut Devices,
+    layout: &Layout,
+    assignments: &[KeyAssignment],
+    base: Color,
+) -> Result<(), Error> {
+    let mut colors = [base; KEYS_COUNT];
+
+    for assignment in assignments {
+        let keys = layout
+            .parse_keys(&assignment.keys)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+        for idx in keys {
+            colors[idx] = assignment.color;
+        }
+    }
+
+    devices.for_each_supported_devices(|dev| dev.set_colors(&colors))
+}
@@ -0,0 +1,64 @@
+use a4keyboard::devices::DeviceInfo;
+use a4keyboard::devices::Devices;
+use a4keyboard::Error;
+use serde::Serialize;
+use std::io;
+
+#[derive(Serialize)]
+struct Entry {
+    sysfs_name: String,
+    bus: String,
+    vid: String,
+    pid: String,
+    hid: String,
+    name: Option<String>,
+    uniq: Option<String>,
+    driver: Option<String>,
+}
+
+impl Entry {
+    fn new(info: &DeviceInfo, driver: Option<&str>) -> Self {
+        Self {
+            sysfs_name: info.sysfs_name().to_owned(),
+            bus: format!("{:04x}", info.bus()),
+            vid: format!("{:04x}", info.vid()),
+            pid: format!("{:04x}", info.pid()),
+            hid: format!("{:04x}", info.hid()),
+            name: info.name().map(ToOwned::to_owned),
+            uniq: info.uniq().map(ToOwned::to_owned),
+            driver: driver.map(ToOwned::to_owned),
+        }
+    }
+}
+
+pub fn run(devices: &Devices, json: bool) -> Result<(), Error> {
+    let mut entries = Vec::new();
+    devices.for_each_devices(|info| {
+        entries.push(Entry::new(info, devices.driver_name(info)));
+        Ok::<(), Error>(())
+    })?;
+
+    entries.sort_by(|a, b| a.sysfs_name.cmp(&b.sysfs_name));
+
+    if json {
+        serde_json::to_writer_pretty(io::stdout(), &entries).map_err(io::Error::from)?;
+        println!();
+        return Ok(());
+    }
+
+    for entry in entries {
+        println!(
+            "{} bus={} vid={} pid={} hid={} name={:?} uniq={:?} driver={}",
+            entry.sysfs_name,
+            entry.bus,
+            entry.vid,
+            entry.pid,
+            entry.hid,
+            entry.name.unwrap_or_default(),
+            entry.uniq.unwrap_or_default(),
+            entry.driver.as_deref().unwrap_or("-"),
+        );
+    }
+
+    Ok(())
+}
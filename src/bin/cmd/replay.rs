@@ -0,0 +1,72 @@
+use a4keyboard::devices::transport::capture::Record;
+use a4keyboard::devices::transport::Transport;
+use a4keyboard::devices::Devices;
+use a4keyboard::Error;
+use std::collections::BTreeSet;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How often termination is checked while waiting for the next report
+const SLEEP_STEP: Duration = Duration::from_millis(50);
+
+/// Sends recorded reports with original timing, `hid` replaces recorded HID IDs
+///
+/// Capture ends with its own release of control, so if replay is stopped before that,
+/// control of replayed devices is released through `devices`.
+pub fn run(
+    devices: &mut Devices,
+    transport: &mut dyn Transport,
+    records: &[Record],
+    hid: Option<u16>,
+    terminated: &AtomicBool,
+) -> Result<(), Error> {
+    let mut replayed = BTreeSet::new();
+    let result = send(transport, records, hid, terminated, &mut replayed);
+
+    if !matches!(result, Ok(true)) {
+        // the first error is more important than errors of release
+        let released = devices.for_each_supported_devices(|dev| {
+            if replayed.contains(&dev.hid()) {
+                dev.release_control()?;
+            }
+            Ok(())
+        });
+        result?;
+        released?;
+    }
+
+    Ok(())
+}
+
+/// Returns `false` if replay is stopped by signal
+fn send(
+    transport: &mut dyn Transport,
+    records: &[Record],
+    hid: Option<u16>,
+    terminated: &AtomicBool,
+    replayed: &mut BTreeSet<u16>,
+) -> Result<bool, Error> {
+    let start = Instant::now();
+
+    for record in records {
+        // waits in short steps, so signal stops replay soon
+        loop {
+            if terminated.load(Ordering::Relaxed) {
+                return Ok(false);
+            }
+            match record.time.checked_sub(start.elapsed()) {
+                Some(delay) if !delay.is_zero() => thread::sleep(delay.min(SLEEP_STEP)),
+                _ => break,
+            }
+        }
+
+        let hid = hid.unwrap_or(record.hid);
+        replayed.insert(hid);
+        transport.write_report(hid, record.report_type, &record.data)?;
+    }
+
+    Ok(true)
+}
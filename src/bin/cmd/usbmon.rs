@@ -0,0 +1,85 @@
+use a4keyboard::devices::bloody::protocol::Command;
+use a4keyboard::devices::transport::dump::hex_dump;
+use a4keyboard::usbmon;
+use a4keyboard::usbmon::Address;
+use a4keyboard::usbmon::SetReport;
+use a4keyboard::usbmon::UsbId;
+use a4keyboard::Error;
+use std::io;
+use std::io::IsTerminal as _;
+use std::io::Write;
+use std::path::Path;
+
+const HIGHLIGHT: &str = "\x1b[1;31m";
+const RESET: &str = "\x1b[0m";
+
+/// Prints SET_REPORT requests of capture, packets which Bloody codec can't decode are
+/// highlighted and dumped
+pub fn run(path: &Path, id: Option<UsbId>, address: Option<Address>) -> Result<(), Error> {
+    let reports = usbmon::load(path)?;
+    let matches = |report: &&SetReport| {
+        id.is_none_or(|id| report.id == Some(id))
+            && address.is_none_or(|address| report.address == address)
+    };
+
+    let color = io::stdout().is_terminal();
+    let mut out = io::stdout().lock();
+    let mut shown = 0;
+    let mut unknown = 0;
+
+    for report in reports.iter().filter(matches) {
+        shown += 1;
+
+        let device = match report.id {
+            Some(id) => id.to_string(),
+            None => "????:????".to_owned(),
+        };
+        write!(
+            out,
+            "{:>4}.{:06} {} {device} {} {:02x}: ",
+            report.time.as_secs(),
+            report.time.subsec_micros(),
+            report.address,
+            report.report_type,
+            report.report_id,
+        )?;
+
+        // usbmon text keeps only beginning of data, which is enough to find out command
+        let mut data = report.data.clone();
+        data.resize(report.length, 0);
+
+        let error = match Command::decode(&data) {
+            Ok(command) if report.is_truncated() => {
+                writeln!(
+                    out,
+                    "bloody {command} (truncated to {} bytes)",
+                    report.data.len()
+                )?;
+                continue;
+            }
+            Ok(command) => {
+                writeln!(out, "bloody {command}")?;
+                continue;
+            }
+            Err(err) => err.to_string(),
+        };
+
+        unknown += 1;
+        if color {
+            writeln!(out, "{HIGHLIGHT}UNKNOWN{RESET} ({error})")?;
+        } else {
+            writeln!(out, "UNKNOWN ({error})")?;
+        }
+        hex_dump(&mut out, &report.data)?;
+    }
+
+    writeln!(out, "{shown} reports, {unknown} unknown")?;
+
+    if shown == 0 && id.is_some() && reports.iter().any(|report| report.id.is_none()) {
+        log::warn!(
+            "capture contains devices without enumeration, try to select them with `--address`"
+        );
+    }
+
+    Ok(())
+}
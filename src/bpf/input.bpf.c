@@ -0,0 +1,68 @@
+/* vmlinux.h is generated from Linux before 6.11, where this name is taken by
+   internal struct, which is renamed to hid_ops since then */
+#define hid_bpf_ops hid_ops
+#include "vmlinux.h"
+#undef hid_bpf_ops
+
+#include <bpf/bpf_helpers.h>
+#include <bpf/bpf_tracing.h>
+
+extern u8 *hid_bpf_get_data(struct hid_bpf_ctx *ctx, unsigned int offset,
+                            const size_t rdwr_buf_size) __ksym;
+
+enum {
+  /* kernel rounds up buffer of reports to 64 bytes, so it's always readable */
+  MAX_REPORT_SIZE = 64,
+  RINGBUF_SIZE = 64 * 1024,
+};
+
+/* only members which are used, libbpf matches them by name with Linux 6.11+ */
+struct hid_bpf_ops {
+  int hid_id;
+  u32 flags;
+  int (*hid_device_event)(struct hid_bpf_ctx *ctx,
+                          enum hid_report_type report_type, u64 source);
+};
+
+/* sample of ring buffer, report is truncated to MAX_REPORT_SIZE */
+struct Event {
+  u32 size;
+  u8 data[MAX_REPORT_SIZE];
+};
+
+struct {
+  __uint(type, BPF_MAP_TYPE_RINGBUF);
+  __uint(max_entries, RINGBUF_SIZE);
+} events SEC(".maps");
+
+SEC("struct_ops/hid_device_event")
+int BPF_PROG(device_event, struct hid_bpf_ctx *hid_ctx,
+             enum hid_report_type report_type, u64 source) {
+  if (report_type != HID_INPUT_REPORT)
+    return 0;
+
+  u8 *data = hid_bpf_get_data(hid_ctx, 0, MAX_REPORT_SIZE);
+  if (!data)
+    return 0;
+
+  /* reports are dropped if reader is too slow */
+  struct Event *event = bpf_ringbuf_reserve(&events, sizeof(*event), 0);
+  if (!event)
+    return 0;
+
+  event->size =
+      hid_ctx->size < MAX_REPORT_SIZE ? hid_ctx->size : MAX_REPORT_SIZE;
+  __builtin_memcpy(event->data, data, MAX_REPORT_SIZE);
+  bpf_ringbuf_submit(event, 0);
+
+  /* report is passed to other drivers unchanged */
+  return 0;
+}
+
+/* hid_id is set by loader, every keyboard gets its own copy of program */
+SEC(".struct_ops.link")
+struct hid_bpf_ops input_ops = {
+    .hid_device_event = (void *)device_event,
+};
+
+char _license[] SEC("license") = "GPL";
@@ -5,37 +5,78 @@
 
 extern struct hid_bpf_ctx *hid_bpf_allocate_context(unsigned int hid_id) __ksym;
 extern void hid_bpf_release_context(struct hid_bpf_ctx *ctx) __ksym;
-extern int hid_bpf_hw_request(struct hid_bpf_ctx *ctx, const u8 *data,
+extern int hid_bpf_hw_request(struct hid_bpf_ctx *ctx, u8 *data,
                               size_t len, enum hid_report_type type,
                               enum hid_class_request reqtype) __ksym;
+/* since Linux 6.11, weak so the program loads on older kernels */
+extern int hid_bpf_hw_output_report(struct hid_bpf_ctx *ctx, u8 *data,
+                                    size_t len) __weak __ksym;
 
 enum {
   MAX_BLOCK_SIZE = 256,
+  /* enough for a whole frame of colors */
+  MAX_BATCH = 8,
+  /* not a HID class request, block is sent with hid_bpf_hw_output_report */
+  REQUEST_OUTPUT_REPORT = 0,
 };
 
 struct Block {
   u8 buf[MAX_BLOCK_SIZE];
 };
 
+/* single map value, so the whole batch is updated with one syscall */
+struct Batch {
+  struct Block blocks[MAX_BATCH];
+};
+
 struct {
   __uint(type, BPF_MAP_TYPE_ARRAY);
   __type(key, u32);
-  __type(value, struct Block);
+  __type(value, struct Batch);
   __uint(max_entries, 1);
 } array SEC(".maps");
 
 struct Hdr {
   unsigned int hid_id;
   unsigned int data_size;
+  /* HID_REQ_SET_REPORT sends block, HID_REQ_GET_REPORT replaces it with reply */
+  unsigned int request;
+  /* enum hid_report_type of HID_REQ_* requests */
+  unsigned int report_type;
+  /* number of blocks of `data_size` bytes which are sent in order,
+     only the first one is used by HID_REQ_GET_REPORT */
+  unsigned int count;
 };
 
+static __always_inline int request(struct hid_bpf_ctx *hid_ctx,
+                                   struct Block *block, u32 data_size,
+                                   u32 req, u32 report_type) {
+  /* both return size of sent or received report */
+  switch (req) {
+  case REQUEST_OUTPUT_REPORT:
+    if (!bpf_ksym_exists(hid_bpf_hw_output_report))
+      return -EOPNOTSUPP;
+
+    return hid_bpf_hw_output_report(hid_ctx, block->buf, data_size);
+  case HID_REQ_SET_REPORT:
+  case HID_REQ_GET_REPORT:
+    if (report_type != HID_OUTPUT_REPORT && report_type != HID_FEATURE_REPORT)
+      return -EINVAL;
+
+    return hid_bpf_hw_request(hid_ctx, block->buf, data_size, report_type,
+                              req);
+  default:
+    return -EINVAL;
+  }
+}
+
 SEC("syscall") int write(struct Hdr *hdr) {
   int ret = 0;
   struct hid_bpf_ctx *hid_ctx = NULL;
   u32 key = 0;
 
-  const struct Block *block = bpf_map_lookup_elem(&array, &key);
-  if (!block) {
+  struct Batch *batch = bpf_map_lookup_elem(&array, &key);
+  if (!batch) {
     ret = -EINVAL;
     goto exit;
   }
@@ -46,13 +87,25 @@ SEC("syscall") int write(struct Hdr *hdr) {
     goto exit;
   }
 
-  if (hdr->data_size >= MAX_BLOCK_SIZE) {
+  /* context is writable by user space, so it's read once and only checked
+     values are used, otherwise verifier loses bounds of size after kfunc calls */
+  u32 data_size = hdr->data_size;
+  u32 req = hdr->request;
+  u32 report_type = hdr->report_type;
+  u32 count = hdr->count;
+
+  if (data_size > MAX_BLOCK_SIZE || count == 0 || count > MAX_BATCH ||
+      (req == HID_REQ_GET_REPORT && count != 1)) {
     ret = -EINVAL;
     goto exit;
   }
 
-  ret = hid_bpf_hw_request(hid_ctx, block->buf, hdr->data_size,
-                           HID_FEATURE_REPORT, HID_REQ_SET_REPORT);
+  /* stops at first failed report, returns size of the last one */
+  for (u32 i = 0; i < MAX_BATCH && i < count; i++) {
+    ret = request(hid_ctx, &batch->blocks[i], data_size, req, report_type);
+    if (ret < 0)
+      break;
+  }
 
 exit:
   if (hid_ctx)
@@ -5,8 +5,9 @@ use std::ffi::OsStr;
 use std::ffi::OsString;
 use std::fmt;
 use std::str;
+use std::str::FromStr;
 
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -42,14 +43,24 @@ impl TypedValueParser for ColorParser {
             .into_string()
             .map_err(|_| clap::Error::new(ErrorKind::InvalidUtf8))?;
 
-        let value = value.as_bytes();
+        value
+            .parse()
+            .map_err(|_| clap::Error::raw(ErrorKind::InvalidValue, "Incorrect color value\n"))
+    }
+}
 
-        fn incorrect_color() -> clap::Error {
-            clap::Error::raw(ErrorKind::InvalidValue, "Incorrect color value\n")
-        }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("incorrect color value")]
+pub struct ParseColorError;
+
+impl FromStr for Color {
+    type Err = ParseColorError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.as_bytes();
 
         let len @ (3 | 6) = value.len() else {
-            return Err(incorrect_color());
+            return Err(ParseColorError);
         };
 
         let (r, g, b) = match len {
@@ -68,9 +79,9 @@ impl TypedValueParser for ColorParser {
             _ => unreachable!(),
         };
 
-        let r = from_hex(&r).ok_or_else(incorrect_color)?;
-        let g = from_hex(&g).ok_or_else(incorrect_color)?;
-        let b = from_hex(&b).ok_or_else(incorrect_color)?;
+        let r = from_hex(&r).ok_or(ParseColorError)?;
+        let g = from_hex(&g).ok_or(ParseColorError)?;
+        let b = from_hex(&b).ok_or(ParseColorError)?;
 
         Ok(Color { r, g, b })
     }
@@ -1,235 +1,602 @@
 use crate::color::Color;
-use crate::utils::AsBytes as _;
-use libbpf_rs::skel::OpenSkel as _;
-use libbpf_rs::skel::SkelBuilder as _;
-use libbpf_rs::Error;
-use libbpf_rs::MapCore as _;
-use libbpf_rs::MapFlags;
-use libbpf_rs::OpenObject;
-use libbpf_rs::ProgramInput;
-use once_cell::sync::Lazy;
+use crate::Error;
+use definition::Definition;
+use selector::DeviceSelector;
 use std::fs;
 use std::io;
-use std::mem::size_of_val;
-use std::mem::MaybeUninit;
-use std::ptr::addr_of_mut;
-use std::ptr::copy_nonoverlapping;
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::path::Path;
+use std::path::PathBuf;
 use std::str;
-use write_bpf::WriteSkelBuilder;
+use std::sync::Arc;
+use transport::ReportType;
+use transport::Transport;
+use transport::TransportKind;
 pub mod bloody;
+pub mod definition;
+pub mod input;
+pub mod selector;
+pub mod testing;
+pub mod transport;
 
 #[path = "bpf/write.bpf.rs"]
 mod write_bpf;
 
+#[path = "bpf/input.bpf.rs"]
+mod input_bpf;
+
+/// Driver object stored in registry, either [`Device`] implementation or [`Definition`]
+trait Driver: Send + Sync {
+    fn name(&self) -> &str;
+    fn probe(&self, info: &DeviceInfo) -> bool;
+    fn gain_control(&self, writer: &mut Writer) -> Result<(), Error>;
+    fn release_control(&self, writer: &mut Writer) -> Result<(), Error>;
+    fn set_colors(&self, writer: &mut Writer, colors: &[Color; 104]) -> Result<(), Error>;
+}
+
 struct DeviceFunctions {
+    name: &'static str,
     probe: fn(&DeviceInfo) -> bool,
     gain_control: fn(&mut Writer) -> Result<(), Error>,
     release_control: fn(&mut Writer) -> Result<(), Error>,
     set_colors: fn(&mut Writer, &[Color; 104]) -> Result<(), Error>,
 }
 
-pub struct DeviceHandle {
-    functions: &'static DeviceFunctions,
-    writer: Writer,
+impl DeviceFunctions {
+    fn of<D: Device>() -> Self {
+        Self {
+            name: D::NAME,
+            probe: D::probe,
+            gain_control: D::gain_control,
+            release_control: D::release_control,
+            set_colors: D::set_colors,
+        }
+    }
+}
+
+impl Driver for DeviceFunctions {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn probe(&self, info: &DeviceInfo) -> bool {
+        (self.probe)(info)
+    }
+
+    fn gain_control(&self, writer: &mut Writer) -> Result<(), Error> {
+        (self.gain_control)(writer)
+    }
+
+    fn release_control(&self, writer: &mut Writer) -> Result<(), Error> {
+        (self.release_control)(writer)
+    }
+
+    fn set_colors(&self, writer: &mut Writer, colors: &[Color; 104]) -> Result<(), Error> {
+        (self.set_colors)(writer, colors)
+    }
+}
+
+pub struct DeviceHandle<'a> {
+    driver: &'a dyn Driver,
+    writer: Writer<'a>,
 }
 
-impl DeviceHandle {
+impl DeviceHandle<'_> {
+    /// HID ID of device
+    pub fn hid(&self) -> u16 {
+        self.writer.hid()
+    }
+
+    /// Name of driver which handles device
+    pub fn driver_name(&self) -> &str {
+        self.driver.name()
+    }
+
     pub fn probe(&self, device_info: &DeviceInfo) -> bool {
-        (self.functions.probe)(device_info)
+        self.driver.probe(device_info)
     }
 
     pub fn gain_control(&mut self) -> Result<(), Error> {
-        (self.functions.gain_control)(&mut self.writer)
+        self.driver.gain_control(&mut self.writer)
     }
 
     pub fn release_control(&mut self) -> Result<(), Error> {
-        (self.functions.release_control)(&mut self.writer)
+        self.driver.release_control(&mut self.writer)
     }
 
     pub fn set_colors(&mut self, colors: &[Color; 104]) -> Result<(), Error> {
-        (self.functions.set_colors)(&mut self.writer, colors)
+        self.driver.set_colors(&mut self.writer, colors)
+    }
+
+    /// Reads feature report from device, see [`Writer::read`]
+    pub fn read_report(&mut self, data: &mut [u8]) -> Result<usize, Error> {
+        self.writer.read(data)
     }
 }
 
-trait Device {
+/// Keyboard driver, registered with [`Devices::register`]
+///
+/// Every HID device is passed to [`Device::probe`] and other functions are called only for
+/// devices which are accepted by it.
+pub trait Device {
+    /// Driver name which is shown by `list` command
+    const NAME: &'static str;
+
+    /// Returns `true` if driver supports device, usually checks VID:PID and report descriptor
     fn probe(info: &DeviceInfo) -> bool;
+
+    /// Switches device to software controlled mode
     fn gain_control(writer: &mut Writer) -> Result<(), Error>;
+
+    /// Returns device to mode controlled by firmware
     fn release_control(writer: &mut Writer) -> Result<(), Error>;
+
+    /// Sends frame, colors are indexed like keys of [`crate::layout::Layout`]
     fn set_colors(writer: &mut Writer, colors: &[Color; 104]) -> Result<(), Error>;
 }
 
-struct Writer {
-    kernel_writer: &'static mut KernelWriter<'static>,
+/// Sends reports to single HID device through selected transport
+pub struct Writer<'a> {
+    transport: &'a mut dyn Transport,
     hid: u16,
 }
 
-impl Writer {
-    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
-        use write_bpf::types::Block;
-        use write_bpf::types::Hdr;
-
-        let mut block = Block::default();
-        if data.len() > size_of_val(&block) {
-            return Err(Error::from(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "data size is more than can be",
-            )));
-        }
-
-        // SAFETY: sizes is checked above
-        unsafe {
-            copy_nonoverlapping(data.as_ptr(), block.as_bytes_mut().as_mut_ptr(), data.len())
-        };
-
-        self.kernel_writer.program.maps.array.update(
-            &[0u8; 4],
-            block.as_bytes(),
-            MapFlags::empty(),
-        )?;
+impl Writer<'_> {
+    /// HID ID of device which reports are sent to
+    pub fn hid(&self) -> u16 {
+        self.hid
+    }
 
-        let mut hdr = Hdr {
-            hid_id: self.hid as u32,
-            data_size: data.len() as u32,
-        };
-        let mut input = ProgramInput::default();
-        input.context_in = Some(hdr.as_bytes_mut());
+    /// Sends feature report, first byte of `data` is report ID
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.transport.write(self.hid, data)
+    }
 
-        self.kernel_writer.program.progs.write.test_run(input)?;
+    /// Sends report the way device expects it, e.g. lighting data of some keyboards is
+    /// accepted only as interrupt OUT reports
+    pub fn write_report(&mut self, report_type: ReportType, data: &[u8]) -> Result<(), Error> {
+        self.transport.write_report(self.hid, report_type, data)
+    }
 
-        Ok(())
+    /// Sends several reports of the same type, e.g. all packets of a frame. Transport may
+    /// deliver them at once, which is faster than [`Writer::write_report`] for each one.
+    pub fn write_batch(&mut self, report_type: ReportType, packets: &[&[u8]]) -> Result<(), Error> {
+        self.transport.write_batch(self.hid, report_type, packets)
     }
-}
 
-struct KernelWriter<'a> {
-    program: write_bpf::WriteSkel<'a>,
+    /// Reads feature report which ID is the first byte of `data`, like firmware version or
+    /// onboard settings. Returns size of reply, `data` is filled with it.
+    pub fn read(&mut self, data: &mut [u8]) -> Result<usize, Error> {
+        self.transport.read(self.hid, data)
+    }
 }
 
 pub struct DeviceInfo {
+    bus: u16,
     hid: u16,
     vid: u16,
     pid: u16,
+    sysfs_name: String,
+    sysfs_path: PathBuf,
+    name: Option<String>,
+    uniq: Option<String>,
     report_descriptor: hrd::Descriptor,
 }
 
-pub struct Devices<'a> {
-    supported_devices: Vec<DeviceFunctions>,
-    kernel_writer: Lazy<KernelWriter<'a>>,
+impl DeviceInfo {
+    pub fn new(hid: u16, vid: u16, pid: u16, report_descriptor: hrd::Descriptor) -> Self {
+        const BUS_USB: u16 = 0x0003;
+
+        let sysfs_name = format!("{BUS_USB:04X}:{vid:04X}:{pid:04X}.{hid:04X}");
+        let sysfs_path = hid_devices_dir(Path::new(DEFAULT_SYSFS_ROOT)).join(&sysfs_name);
+
+        Self {
+            bus: BUS_USB,
+            hid,
+            vid,
+            pid,
+            sysfs_name,
+            sysfs_path,
+            name: None,
+            uniq: None,
+            report_descriptor,
+        }
+    }
+
+    pub fn bus(&self) -> u16 {
+        self.bus
+    }
+
+    pub fn hid(&self) -> u16 {
+        self.hid
+    }
+
+    pub fn vid(&self) -> u16 {
+        self.vid
+    }
+
+    pub fn pid(&self) -> u16 {
+        self.pid
+    }
+
+    /// Name of device directory in sysfs, like `0003:09DA:FA10.0005`
+    pub fn sysfs_name(&self) -> &str {
+        &self.sysfs_name
+    }
+
+    /// Path to device directory in sysfs
+    pub fn sysfs_path(&self) -> &Path {
+        &self.sysfs_path
+    }
+
+    /// `HID_NAME` from `uevent`
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// `HID_UNIQ` from `uevent`, usually serial number
+    pub fn uniq(&self) -> Option<&str> {
+        self.uniq.as_deref()
+    }
+
+    pub fn report_descriptor(&self) -> &hrd::Descriptor {
+        &self.report_descriptor
+    }
+
+    fn read_uevent(&mut self) -> io::Result<()> {
+        let uevent = fs::read_to_string(self.sysfs_path.join("uevent"))?;
+
+        for line in uevent.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            let value = (!value.is_empty()).then(|| value.to_owned());
+            match key {
+                "HID_NAME" => self.name = value,
+                "HID_UNIQ" => self.uniq = value,
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns control of all supported devices to firmware when dropped
+#[must_use = "control is released immediately if guard is dropped"]
+pub struct ControlGuard<'a> {
+    devices: &'a mut Devices,
+    /// Devices which gained control with their drivers, they are released even if they
+    /// don't match selectors anymore, and devices plugged in later aren't touched
+    controlled: Vec<(u16, Arc<dyn Driver>)>,
+}
+
+impl ControlGuard<'_> {
+    /// Leaves devices controlled by software, so last frame stays on them after exit
+    pub fn keep(self) {
+        std::mem::forget(self);
+    }
 }
 
-impl KernelWriter<'_> {
-    fn new() -> Self {
-        static mut OBJECT: MaybeUninit<OpenObject> = MaybeUninit::uninit();
+impl Deref for ControlGuard<'_> {
+    type Target = Devices;
 
-        let program = WriteSkelBuilder::default()
-            .open(
-                // SAFETY: should be called once
-                unsafe { &mut *addr_of_mut!(OBJECT) },
-            )
-            .unwrap();
+    fn deref(&self) -> &Devices {
+        self.devices
+    }
+}
+
+impl DerefMut for ControlGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Devices {
+        self.devices
+    }
+}
 
-        let program = program.load().unwrap();
+impl Drop for ControlGuard<'_> {
+    fn drop(&mut self) {
+        let Devices {
+            transport_kind,
+            transport,
+            sysfs_root,
+            ..
+        } = &mut *self.devices;
+
+        for (hid, driver) in &self.controlled {
+            let result =
+                open_transport(transport, *transport_kind, sysfs_root).and_then(|transport| {
+                    let mut dev = DeviceHandle {
+                        driver: driver.as_ref(),
+                        writer: Writer {
+                            hid: *hid,
+                            transport,
+                        },
+                    };
+                    dev.release_control()
+                });
 
-        Self { program }
+            if let Err(err) = result {
+                log::error!("HID {hid:04X}: failed to release control: {err}");
+            }
+        }
     }
 }
 
-static mut DEVICES: Lazy<Devices<'static>> = Lazy::new(|| Devices {
-    supported_devices: Vec::new(),
-    kernel_writer: Lazy::new(KernelWriter::new),
-});
+/// Registry of drivers together with settings of device lookup and transport
+pub struct Devices {
+    drivers: Vec<Arc<dyn Driver>>,
+    transport_kind: TransportKind,
+    transport: Option<Box<dyn Transport>>,
+    sysfs_root: PathBuf,
+    selectors: Vec<DeviceSelector>,
+}
+
+pub const DEFAULT_SYSFS_ROOT: &str = "/sys";
+
+fn hid_devices_dir(sysfs_root: &Path) -> PathBuf {
+    sysfs_root.join("bus/hid/devices")
+}
 
 fn from_hex(data: &[u8]) -> Option<u16> {
     u16::from_str_radix(str::from_utf8(data).ok()?, 16).ok()
 }
 
-impl Devices<'_> {
-    fn instance() -> &'static mut Devices<'static> {
-        // SAFETY: application is single threaded and mutable only at startup
-        unsafe { &mut *DEVICES }
-    }
+/// Returns the first registered driver which supports device
+fn find_driver<'a>(
+    drivers: &'a [Arc<dyn Driver>],
+    info: &DeviceInfo,
+) -> Option<&'a Arc<dyn Driver>> {
+    drivers.iter().find(|driver| driver.probe(info))
+}
 
-    fn register<D: Device>() {
-        Self::instance().supported_devices.push(DeviceFunctions {
-            probe: D::probe,
-            gain_control: D::gain_control,
-            release_control: D::release_control,
-            set_colors: D::set_colors,
-        });
-    }
+/// Calls `f` for every HID device in `sysfs_root` which matches `selectors`
+fn enumerate<E: From<Error>>(
+    sysfs_root: &Path,
+    selectors: &[DeviceSelector],
+    mut f: impl FnMut(&DeviceInfo) -> Result<(), E>,
+) -> Result<(), E> {
+    let path = hid_devices_dir(sysfs_root);
+    let dir = match fs::read_dir(&path) {
+        Ok(dir) => dir,
+        Err(source) => return Err(E::from(Error::Enumeration { path, source })),
+    };
+    for device_dir in dir {
+        let Ok(device_dir) = device_dir else {
+            continue;
+        };
+        let name = device_dir.file_name();
+        let name = name.as_encoded_bytes();
+
+        if name.len() != 19 {
+            continue;
+        }
 
-    fn for_each_devices<E>(mut f: impl FnMut(&DeviceInfo) -> Result<(), E>) -> Result<(), E> {
-        let dir = fs::read_dir("/sys/bus/hid/devices").unwrap();
-        for device_dir in dir {
-            let Ok(device_dir) = device_dir else {
+        let bus = &name[0..4];
+        let vid = &name[5..9];
+        let pid = &name[10..14];
+        let hid = &name[15..19];
+
+        let (Some(bus), Some(vid), Some(pid), Some(hid)) =
+            (from_hex(bus), from_hex(vid), from_hex(pid), from_hex(hid))
+        else {
+            continue;
+        };
+
+        let dev_name = String::from_utf8_lossy(name).into_owned();
+
+        let report_descriptor = match fs::read(device_dir.path().join("report_descriptor")) {
+            Ok(report_descriptor) => report_descriptor,
+            Err(err) => {
+                log::error!("{dev_name}: {err}");
                 continue;
-            };
-            let name = device_dir.file_name();
-            let name = name.as_encoded_bytes();
+            }
+        };
 
-            if name.len() != 19 {
+        let report_descriptor = match hrd::parse(report_descriptor.as_slice()) {
+            Ok(report_descriptor) => report_descriptor,
+            Err(source) => {
+                let err = Error::Descriptor {
+                    device: dev_name,
+                    source,
+                };
+                log::error!("{err}");
                 continue;
             }
+        };
 
-            let _bus = &name[0..4];
-            let vid = &name[5..9];
-            let pid = &name[10..14];
-            let hid = &name[15..19];
-
-            // SAFETY: vid, pid and hid is valid hex strings
-            let vid = unsafe { from_hex(vid).unwrap_unchecked() };
-            let pid = unsafe { from_hex(pid).unwrap_unchecked() };
-            let hid = unsafe { from_hex(hid).unwrap_unchecked() };
-
-            let report_descriptor = match fs::read(device_dir.path().join("report_descriptor")) {
-                Ok(report_descriptor) => report_descriptor,
-                Err(err) => {
-                    let dev_name = device_dir.file_name().into_string().unwrap();
-                    log::error!("{dev_name}: {err}");
-                    continue;
-                }
-            };
+        let mut info = DeviceInfo {
+            bus,
+            hid,
+            vid,
+            pid,
+            sysfs_name: dev_name,
+            sysfs_path: device_dir.path(),
+            name: None,
+            uniq: None,
+            report_descriptor,
+        };
 
-            let report_descriptor = match hrd::parse(report_descriptor.as_slice()) {
-                Ok(report_descriptor) => report_descriptor,
-                Err(err) => {
-                    let dev_name = device_dir.file_name().into_string().unwrap();
-                    log::error!("{dev_name}: {err}");
-                    continue;
-                }
-            };
+        if let Err(err) = info.read_uevent() {
+            log::warn!("{}: uevent: {err}", info.sysfs_name);
+        }
 
-            let info = DeviceInfo {
-                hid,
-                vid,
-                pid,
-                report_descriptor,
-            };
+        let selected =
+            selectors.is_empty() || selectors.iter().any(|selector| selector.matches(&info));
+        if !selected {
+            continue;
+        }
+
+        f(&info)?;
+    }
+
+    Ok(())
+}
+
+/// Opens transport on first use
+fn open_transport<'a>(
+    transport: &'a mut Option<Box<dyn Transport>>,
+    kind: TransportKind,
+    sysfs_root: &Path,
+) -> Result<&'a mut dyn Transport, Error> {
+    let opened = match transport.take() {
+        Some(opened) => opened,
+        None => kind.open(sysfs_root)?,
+    };
+
+    Ok(transport.insert(opened).as_mut())
+}
+
+impl Default for Devices {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Devices {
+    /// Creates registry with all built-in drivers
+    pub fn new() -> Self {
+        let mut devices = Self::empty();
+        devices.register::<bloody::Bloody>();
+        devices
+    }
 
-            f(&info)?;
+    /// Creates registry without drivers
+    pub fn empty() -> Self {
+        Self {
+            drivers: Vec::new(),
+            transport_kind: TransportKind::default(),
+            transport: None,
+            sysfs_root: PathBuf::from(DEFAULT_SYSFS_ROOT),
+            selectors: Vec::new(),
         }
+    }
 
-        Ok(())
+    /// Select backend which will be used for sending reports
+    pub fn set_transport(&mut self, kind: TransportKind) {
+        self.transport_kind = kind;
+        self.transport = None;
+    }
+
+    /// Use already created backend for sending reports
+    pub fn use_transport(&mut self, transport: Box<dyn Transport>) {
+        self.transport = Some(transport);
+    }
+
+    /// Set path where sysfs is mounted, HID devices are looked up in `bus/hid/devices` inside it
+    pub fn set_sysfs_root(&mut self, path: impl Into<PathBuf>) {
+        self.sysfs_root = path.into();
+    }
+
+    /// Adds driver, drivers are probed in order of registration
+    pub fn register<D: Device>(&mut self) {
+        self.drivers.push(Arc::new(DeviceFunctions::of::<D>()));
+    }
+
+    /// Adds data-driven driver
+    pub fn register_definition(&mut self, definition: Definition) {
+        self.drivers.push(Arc::new(definition));
+    }
+
+    /// Registers every `*.toml` definition from `dir` in order of file names
+    ///
+    /// Missing directory isn't an error, returns number of loaded definitions
+    pub fn load_definitions(&mut self, dir: impl AsRef<Path>) -> Result<usize, Error> {
+        let definitions = definition::load_dir(dir.as_ref())?;
+        let count = definitions.len();
+        for definition in definitions {
+            self.register_definition(definition);
+        }
+
+        Ok(count)
+    }
+
+    fn find_driver(&self, info: &DeviceInfo) -> Option<&Arc<dyn Driver>> {
+        find_driver(&self.drivers, info)
+    }
+
+    /// Limit devices to ones which match any of `selectors`, empty list means all devices
+    pub fn set_selectors(&mut self, selectors: Vec<DeviceSelector>) {
+        self.selectors = selectors;
+    }
+
+    /// Returns name of first registered driver which supports device
+    pub fn driver_name(&self, info: &DeviceInfo) -> Option<&str> {
+        self.find_driver(info).map(|driver| driver.name())
+    }
+
+    /// Calls `f` for every selected HID device with readable report descriptor
+    ///
+    /// Devices with malformed report descriptors are logged and skipped
+    pub fn for_each_devices<E: From<Error>>(
+        &self,
+        f: impl FnMut(&DeviceInfo) -> Result<(), E>,
+    ) -> Result<(), E> {
+        enumerate(&self.sysfs_root, &self.selectors, f)
+    }
+
+    /// Gains control of all supported devices until returned guard is dropped
+    ///
+    /// Fails with [`Error::NoSupportedDevices`] if there is nothing to control
+    pub fn gain_control(&mut self) -> Result<ControlGuard<'_>, Error> {
+        // created first for releasing devices which gained control before error
+        let mut guard = ControlGuard {
+            devices: self,
+            controlled: Vec::new(),
+        };
+
+        let ControlGuard {
+            devices,
+            controlled,
+        } = &mut guard;
+        devices.for_each_driver(|driver, dev| {
+            // device which fails halfway is released too
+            controlled.push((dev.hid(), Arc::clone(driver)));
+            dev.gain_control()
+        })?;
+
+        if guard.controlled.is_empty() {
+            return Err(Error::NoSupportedDevices);
+        }
+
+        Ok(guard)
     }
 
     pub fn for_each_supported_devices(
+        &mut self,
         mut f: impl FnMut(&mut DeviceHandle) -> Result<(), Error>,
     ) -> Result<(), Error> {
-        Self::for_each_devices(|info| {
-            for functions in &Self::instance().supported_devices {
-                if (functions.probe)(info) {
-                    let mut dev = DeviceHandle {
-                        functions,
-                        writer: Writer {
-                            hid: info.hid,
-                            kernel_writer: &mut Self::instance().kernel_writer,
-                        },
-                    };
+        self.for_each_driver(|_, dev| f(dev))
+    }
 
-                    f(&mut dev)?;
-                }
-            }
+    /// The same as [`Devices::for_each_supported_devices`], also passes driver of device
+    fn for_each_driver(
+        &mut self,
+        mut f: impl FnMut(&Arc<dyn Driver>, &mut DeviceHandle) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let Self {
+            drivers,
+            transport_kind,
+            transport,
+            sysfs_root,
+            selectors,
+        } = self;
+
+        enumerate(sysfs_root, selectors, |info| {
+            // built-in drivers are registered first and take precedence over definitions
+            let Some(driver) = find_driver(drivers, info) else {
+                return Ok(());
+            };
+
+            let mut dev = DeviceHandle {
+                driver: driver.as_ref(),
+                writer: Writer {
+                    hid: info.hid,
+                    transport: open_transport(transport, *transport_kind, sysfs_root)?,
+                },
+            };
 
-            Ok::<(), Error>(())
+            f(driver, &mut dev)
         })
     }
 }
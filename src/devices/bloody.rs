@@ -1,44 +1,28 @@
 use crate::color::Color;
+use crate::devices::transport::ReportType;
 use crate::devices::Device;
 use crate::devices::DeviceInfo;
-use crate::devices::Devices;
 use crate::devices::Writer;
-use crate::utils::startup;
 use crate::Error;
+use protocol::Command;
 
-struct Bloody;
+pub mod emulator;
+pub mod protocol;
 
-startup! {
-    Devices::register::<Bloody>();
-}
+pub(super) struct Bloody;
 
 impl Bloody {
     fn take_control(writer: &mut Writer, value: bool) -> Result<(), Error> {
-        writer.write(&make_prepared_control_packet(0x01))?;
-
-        let mut buf = make_prepared_control_packet(0x00);
-        if value {
-            buf[8] = 0x01;
-        }
-        writer.write(&buf)?;
+        writer.write(&Command::PrepareControl.encode())?;
+        writer.write(&Command::SetControl { software: value }.encode())?;
 
         Ok(())
     }
 }
 
-const fn make_prepared_control_packet(target: u8) -> [u8; 64] {
-    let mut buf = [0u8; 64];
-    buf[0] = 0x07;
-    buf[1] = 0x03;
-    buf[2] = 0x06;
-    buf[3] = target;
-    buf
-}
-
-const BLOODY_B820R_RGB_OFFSET: usize = 6;
-const BLOODY_B820R_RGB_BUFFER_SIZE: usize = 58;
-
 impl Device for Bloody {
+    const NAME: &'static str = "bloody";
+
     fn probe(info: &DeviceInfo) -> bool {
         if info.vid != 0x09da || info.pid != 0xfa10 {
             return false;
@@ -58,35 +42,106 @@ impl Device for Bloody {
     }
 
     fn set_colors(writer: &mut Writer, colors: &[Color; 104]) -> Result<(), Error> {
-        let mut r1_buf = make_prepared_control_packet(0x07);
-        let mut g1_buf = make_prepared_control_packet(0x09);
-        let mut b1_buf = make_prepared_control_packet(0x0B);
-        let mut r2_buf = make_prepared_control_packet(0x08);
-        let mut g2_buf = make_prepared_control_packet(0x0A);
-        let mut b2_buf = make_prepared_control_packet(0x0C);
-
-        let buffers = [
-            [&mut r1_buf, &mut g1_buf, &mut b1_buf],
-            [&mut r2_buf, &mut g2_buf, &mut b2_buf],
-        ];
-
-        for (i, color) in colors.iter().enumerate() {
-            let offset = BLOODY_B820R_RGB_OFFSET;
-            let buffer_idx = offset + i % BLOODY_B820R_RGB_BUFFER_SIZE;
-            let line = i / BLOODY_B820R_RGB_BUFFER_SIZE;
-
-            buffers[line][0][buffer_idx] = color.r;
-            buffers[line][1][buffer_idx] = color.g;
-            buffers[line][2][buffer_idx] = color.b;
+        let packets = Command::frame(colors).map(|command| command.encode());
+        writer.write_batch(
+            ReportType::Feature,
+            &packets.each_ref().map(|data| &data[..]),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::color::Color;
+    use crate::devices::testing::Harness;
+    use crate::devices::transport::Packet;
+    use crate::devices::transport::ReportType;
+    use crate::devices::DeviceInfo;
+
+    const REPORT_DESCRIPTOR: &[u8] = &[
+        0x05, 0x01, 0x09, 0x06, 0xa1, 0x01, 0x85, 0x01, 0x05, 0x07, 0x19, 0xe0, 0x29, 0xe7, 0x15,
+        0x00, 0x25, 0x01, 0x75, 0x01, 0x95, 0x08, 0x81, 0x02, 0x05, 0x07, 0x19, 0x00, 0x29, 0x97,
+        0x15, 0x00, 0x25, 0x01, 0x75, 0x01, 0x96, 0x98, 0x00, 0x81, 0x02, 0xc0, 0x05, 0x01, 0x09,
+        0x80, 0xa1, 0x01, 0x85, 0x02, 0x19, 0x00, 0x29, 0xb7, 0x15, 0x00, 0x26, 0xb7, 0x00, 0x95,
+        0x01, 0x75, 0x08, 0x81, 0x00, 0xc0, 0x05, 0x0c, 0x09, 0x01, 0xa1, 0x01, 0x85, 0x03, 0x1a,
+        0x00, 0x00, 0x2a, 0x3c, 0x02, 0x15, 0x00, 0x26, 0x3c, 0x02, 0x75, 0x10, 0x95, 0x01, 0x81,
+        0x00, 0xc0, 0x06, 0x52, 0xff, 0x0a, 0x10, 0x02, 0xa1, 0x01, 0x85, 0x07, 0x19, 0x01, 0x29,
+        0x3f, 0x15, 0x00, 0x26, 0xff, 0x00, 0x75, 0x08, 0x95, 0x3f, 0x81, 0x00, 0x19, 0x01, 0x29,
+        0x3f, 0x15, 0x00, 0x26, 0xff, 0x00, 0x75, 0x08, 0x95, 0x3f, 0xb1, 0x02, 0xc0,
+    ];
+
+    const HID: u16 = 0x0005;
+
+    fn harness() -> Harness {
+        let descriptor = hrd::parse(REPORT_DESCRIPTOR).unwrap();
+        let info = DeviceInfo::new(HID, 0x09da, 0xfa10, descriptor);
+
+        Harness::probe(&info).unwrap()
+    }
+
+    fn packet(target: u8, payload: &[(usize, u8)]) -> Packet {
+        let mut data = vec![0u8; 64];
+        data[..4].copy_from_slice(&[0x07, 0x03, 0x06, target]);
+        for &(idx, value) in payload {
+            data[idx] = value;
         }
 
-        writer.write(&r1_buf)?;
-        writer.write(&r2_buf)?;
-        writer.write(&g1_buf)?;
-        writer.write(&g2_buf)?;
-        writer.write(&b1_buf)?;
-        writer.write(&b2_buf)?;
+        Packet {
+            hid: HID,
+            report_type: ReportType::Feature,
+            data,
+        }
+    }
 
-        Ok(())
+    #[test]
+    fn probe() {
+        let descriptor = hrd::parse(REPORT_DESCRIPTOR).unwrap();
+        let info = DeviceInfo::new(HID, 0x09da, 0xfa11, descriptor);
+        assert!(Harness::probe(&info).is_none());
+
+        let descriptor = hrd::parse(&REPORT_DESCRIPTOR[..92]).unwrap();
+        let info = DeviceInfo::new(HID, 0x09da, 0xfa10, descriptor);
+        assert!(Harness::probe(&info).is_none());
+    }
+
+    #[test]
+    fn gain_control() {
+        let packets = harness().gain_control().unwrap();
+
+        assert_eq!(packets, [packet(0x01, &[]), packet(0x00, &[(8, 0x01)])]);
+    }
+
+    #[test]
+    fn release_control() {
+        let packets = harness().release_control().unwrap();
+
+        assert_eq!(packets, [packet(0x01, &[]), packet(0x00, &[])]);
+    }
+
+    #[test]
+    fn set_colors() {
+        let mut colors = [Color::default(); 104];
+        colors[0] = Color { r: 1, g: 2, b: 3 };
+        colors[57] = Color { r: 4, g: 5, b: 6 };
+        colors[58] = Color { r: 7, g: 8, b: 9 };
+        colors[103] = Color {
+            r: 10,
+            g: 11,
+            b: 12,
+        };
+
+        let packets = harness().set_colors(&colors).unwrap();
+
+        assert_eq!(
+            packets,
+            [
+                packet(0x07, &[(6, 1), (63, 4)]),
+                packet(0x08, &[(6, 7), (51, 10)]),
+                packet(0x09, &[(6, 2), (63, 5)]),
+                packet(0x0A, &[(6, 8), (51, 11)]),
+                packet(0x0B, &[(6, 3), (63, 6)]),
+                packet(0x0C, &[(6, 9), (51, 12)]),
+            ]
+        );
     }
 }
@@ -0,0 +1,212 @@
+//! Software model of Bloody keyboard for testing without hardware
+
+use super::protocol::Command;
+use super::protocol::DecodeError;
+use super::protocol::LEDS_COUNT;
+use crate::color::Color;
+use crate::devices::transport::Transport;
+use crate::Error;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum EmulatorError {
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+    #[error("control is changed without preparation")]
+    NotPrepared,
+    #[error("colors are sent while keyboard is controlled by firmware")]
+    NotControlled,
+}
+
+/// State of one emulated keyboard
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keyboard {
+    prepared: bool,
+    software_control: bool,
+    frame: [Color; LEDS_COUNT],
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Self {
+            prepared: false,
+            software_control: false,
+            frame: [Color::default(); LEDS_COUNT],
+        }
+    }
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handles one feature report, keeps state unchanged if packet is rejected
+    pub fn feed(&mut self, packet: &[u8]) -> Result<(), EmulatorError> {
+        let command = Command::decode(packet)?;
+
+        match command {
+            Command::PrepareControl => self.prepared = true,
+            Command::SetControl { software } => {
+                if !self.prepared {
+                    return Err(EmulatorError::NotPrepared);
+                }
+                self.prepared = false;
+                self.software_control = software;
+            }
+            Command::Colors { .. } => {
+                if !self.software_control {
+                    return Err(EmulatorError::NotControlled);
+                }
+                command.apply(&mut self.frame);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `true` if colors are set by software instead of firmware effects
+    pub fn is_controlled(&self) -> bool {
+        self.software_control
+    }
+
+    /// Colors of keys which were received last
+    pub fn frame(&self) -> &[Color; LEDS_COUNT] {
+        &self.frame
+    }
+}
+
+/// Transport which passes reports to emulated keyboards, one per HID ID
+///
+/// Rejected reports fail with [`Error::Transport`] which wraps [`EmulatorError`].
+///
+/// Clones share keyboards, so one clone can be given to [`crate::devices::Devices`]
+/// and another one used for checking state.
+#[derive(Clone, Default)]
+pub struct Emulator {
+    keyboards: Arc<Mutex<BTreeMap<u16, Keyboard>>>,
+}
+
+impl Emulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Snapshot of keyboard which received reports for `hid`
+    pub fn keyboard(&self, hid: u16) -> Option<Keyboard> {
+        self.keyboards.lock().unwrap().get(&hid).cloned()
+    }
+
+    /// HID IDs of keyboards which received any report
+    pub fn hids(&self) -> Vec<u16> {
+        self.keyboards.lock().unwrap().keys().copied().collect()
+    }
+}
+
+impl Transport for Emulator {
+    fn write(&mut self, hid: u16, data: &[u8]) -> Result<(), Error> {
+        let mut keyboards = self.keyboards.lock().unwrap();
+
+        keyboards
+            .entry(hid)
+            .or_default()
+            .feed(data)
+            .map_err(|err| Error::Transport {
+                hid,
+                source: io::Error::new(io::ErrorKind::InvalidData, err),
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::EmulatorError;
+    use super::Keyboard;
+    use crate::color::Color;
+    use crate::devices::bloody::protocol::Command;
+    use crate::devices::bloody::protocol::DecodeError;
+
+    #[test]
+    fn control() {
+        let mut keyboard = Keyboard::new();
+        assert!(!keyboard.is_controlled());
+
+        assert_eq!(
+            keyboard.feed(&Command::SetControl { software: true }.encode()),
+            Err(EmulatorError::NotPrepared)
+        );
+
+        keyboard.feed(&Command::PrepareControl.encode()).unwrap();
+        keyboard
+            .feed(&Command::SetControl { software: true }.encode())
+            .unwrap();
+        assert!(keyboard.is_controlled());
+
+        keyboard.feed(&Command::PrepareControl.encode()).unwrap();
+        keyboard
+            .feed(&Command::SetControl { software: false }.encode())
+            .unwrap();
+        assert!(!keyboard.is_controlled());
+    }
+
+    #[test]
+    fn frame() {
+        let mut colors = [Color::default(); 104];
+        colors[3] = Color { r: 1, g: 2, b: 3 };
+        colors[100] = Color { r: 4, g: 5, b: 6 };
+
+        let mut keyboard = Keyboard::new();
+        let [red, ..] = Command::frame(&colors);
+        assert_eq!(
+            keyboard.feed(&red.encode()),
+            Err(EmulatorError::NotControlled)
+        );
+
+        keyboard.feed(&Command::PrepareControl.encode()).unwrap();
+        keyboard
+            .feed(&Command::SetControl { software: true }.encode())
+            .unwrap();
+        for command in Command::frame(&colors) {
+            keyboard.feed(&command.encode()).unwrap();
+        }
+        assert_eq!(keyboard.frame(), &colors);
+    }
+
+    #[test]
+    fn malformed() {
+        let mut keyboard = Keyboard::new();
+
+        let mut packet = Command::PrepareControl.encode();
+        packet[3] = 0x42;
+        assert_eq!(
+            keyboard.feed(&packet),
+            Err(EmulatorError::Decode(DecodeError::Target(0x42)))
+        );
+        assert_eq!(
+            keyboard.feed(&packet[..10]),
+            Err(EmulatorError::Decode(DecodeError::Size(10)))
+        );
+        assert_eq!(keyboard, Keyboard::new());
+    }
+
+    #[test]
+    fn reserved() {
+        let mut keyboard = Keyboard::new();
+        keyboard.feed(&Command::PrepareControl.encode()).unwrap();
+
+        // unknown command which looks like software control
+        let mut packet = Command::SetControl { software: true }.encode();
+        packet[4] = 0x01;
+        assert_eq!(
+            keyboard.feed(&packet),
+            Err(EmulatorError::Decode(DecodeError::Reserved {
+                offset: 4,
+                value: 0x01
+            }))
+        );
+        assert!(!keyboard.is_controlled());
+    }
+}
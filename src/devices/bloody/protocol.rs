@@ -0,0 +1,400 @@
+//! Feature reports of Bloody keyboards
+//!
+//! Every packet is 64 bytes long and starts with `07 03 06 TARGET`, where target selects
+//! command. Colors are sent as six packets, one per channel and half of keys.
+
+use crate::color::Color;
+use std::fmt;
+use std::ops::Range;
+
+pub const PACKET_SIZE: usize = 64;
+pub const REPORT_ID: u8 = 0x07;
+/// First values of every packet
+pub const HEADER: [u8; 3] = [REPORT_ID, 0x03, 0x06];
+/// Offset of first color value in [`Command::Colors`] packet
+pub const COLORS_OFFSET: usize = 6;
+pub const LEDS_PER_PACKET: usize = 58;
+pub const LEDS_COUNT: usize = 104;
+
+const TARGET_OFFSET: usize = 3;
+const CONTROL_OFFSET: usize = 8;
+
+const TARGET_SET_CONTROL: u8 = 0x00;
+const TARGET_PREPARE_CONTROL: u8 = 0x01;
+const TARGET_COLORS: u8 = 0x07;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+}
+
+/// Part of keys which colors are sent in one packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Half {
+    /// Keys `0..58`
+    First,
+    /// Keys `58..104`
+    Second,
+}
+
+impl Half {
+    fn first_led(self) -> usize {
+        match self {
+            Half::First => 0,
+            Half::Second => LEDS_PER_PACKET,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Sent before [`Command::SetControl`]
+    PrepareControl,
+    /// Switches between colors set by software and firmware effects
+    SetControl { software: bool },
+    /// Values of one channel, keys which don't exist in [`Half::Second`] are zero
+    Colors {
+        channel: Channel,
+        half: Half,
+        values: [u8; LEDS_PER_PACKET],
+    },
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::PrepareControl => write!(f, "prepare control"),
+            Command::SetControl { software: true } => write!(f, "set control: software"),
+            Command::SetControl { software: false } => write!(f, "set control: firmware"),
+            Command::Colors { channel, half, .. } => {
+                let channel = match channel {
+                    Channel::Red => "red",
+                    Channel::Green => "green",
+                    Channel::Blue => "blue",
+                };
+                let first = half.first_led();
+                let last = (first + LEDS_PER_PACKET).min(LEDS_COUNT);
+                write!(f, "colors: {channel} {first}..{last}")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DecodeError {
+    #[error("packet size is {0}, expected {PACKET_SIZE}")]
+    Size(usize),
+    #[error("unknown header {0:02x?}")]
+    Header([u8; 3]),
+    #[error("unknown target {0:#04x}")]
+    Target(u8),
+    #[error("unknown control value {0:#04x}")]
+    Control(u8),
+    #[error("reserved byte {offset} is {value:#04x}")]
+    Reserved { offset: usize, value: u8 },
+}
+
+/// Checks that bytes of `packet` in `range`, which aren't used by command, are zero
+fn check_reserved(packet: &[u8], range: Range<usize>) -> Result<(), DecodeError> {
+    let offset = range.start;
+    match packet[range].iter().position(|&value| value != 0) {
+        Some(i) => Err(DecodeError::Reserved {
+            offset: offset + i,
+            value: packet[offset + i],
+        }),
+        None => Ok(()),
+    }
+}
+
+impl Command {
+    /// Packets which set all keys, in order of sending
+    pub fn frame(colors: &[Color; LEDS_COUNT]) -> [Command; 6] {
+        let channel = |channel, half| {
+            let first = Half::first_led(half);
+            let mut values = [0u8; LEDS_PER_PACKET];
+            for (value, color) in values.iter_mut().zip(&colors[first..]) {
+                *value = match channel {
+                    Channel::Red => color.r,
+                    Channel::Green => color.g,
+                    Channel::Blue => color.b,
+                };
+            }
+
+            Command::Colors {
+                channel,
+                half,
+                values,
+            }
+        };
+
+        [
+            channel(Channel::Red, Half::First),
+            channel(Channel::Red, Half::Second),
+            channel(Channel::Green, Half::First),
+            channel(Channel::Green, Half::Second),
+            channel(Channel::Blue, Half::First),
+            channel(Channel::Blue, Half::Second),
+        ]
+    }
+
+    pub fn target(&self) -> u8 {
+        match self {
+            Command::SetControl { .. } => TARGET_SET_CONTROL,
+            Command::PrepareControl => TARGET_PREPARE_CONTROL,
+            Command::Colors { channel, half, .. } => {
+                TARGET_COLORS + *channel as u8 * 2 + *half as u8
+            }
+        }
+    }
+
+    pub fn encode(&self) -> [u8; PACKET_SIZE] {
+        let mut packet = [0u8; PACKET_SIZE];
+        packet[..HEADER.len()].copy_from_slice(&HEADER);
+        packet[TARGET_OFFSET] = self.target();
+
+        match self {
+            Command::PrepareControl => {}
+            Command::SetControl { software } => packet[CONTROL_OFFSET] = *software as u8,
+            Command::Colors { values, .. } => {
+                packet[COLORS_OFFSET..][..LEDS_PER_PACKET].copy_from_slice(values)
+            }
+        }
+
+        packet
+    }
+
+    /// Decodes packet which is encoded by [`Command::encode`], packets with other data in
+    /// unused bytes are rejected
+    pub fn decode(packet: &[u8]) -> Result<Self, DecodeError> {
+        let packet: &[u8; PACKET_SIZE] = packet
+            .try_into()
+            .map_err(|_| DecodeError::Size(packet.len()))?;
+
+        let header = [packet[0], packet[1], packet[2]];
+        if header != HEADER {
+            return Err(DecodeError::Header(header));
+        }
+
+        let target = packet[TARGET_OFFSET];
+        match target {
+            TARGET_PREPARE_CONTROL => {
+                check_reserved(packet, TARGET_OFFSET + 1..PACKET_SIZE)?;
+                Ok(Command::PrepareControl)
+            }
+            TARGET_SET_CONTROL => {
+                check_reserved(packet, TARGET_OFFSET + 1..CONTROL_OFFSET)?;
+                check_reserved(packet, CONTROL_OFFSET + 1..PACKET_SIZE)?;
+                match packet[CONTROL_OFFSET] {
+                    0x00 => Ok(Command::SetControl { software: false }),
+                    0x01 => Ok(Command::SetControl { software: true }),
+                    value => Err(DecodeError::Control(value)),
+                }
+            }
+            0x07..=0x0C => {
+                let index = target - TARGET_COLORS;
+                let channel = match index / 2 {
+                    0 => Channel::Red,
+                    1 => Channel::Green,
+                    _ => Channel::Blue,
+                };
+                let half = match index % 2 {
+                    0 => Half::First,
+                    _ => Half::Second,
+                };
+
+                check_reserved(packet, TARGET_OFFSET + 1..COLORS_OFFSET)?;
+                // keys after the last one don't exist
+                let leds = LEDS_COUNT.min(half.first_led() + LEDS_PER_PACKET) - half.first_led();
+                check_reserved(packet, COLORS_OFFSET + leds..PACKET_SIZE)?;
+
+                let mut values = [0u8; LEDS_PER_PACKET];
+                values.copy_from_slice(&packet[COLORS_OFFSET..][..LEDS_PER_PACKET]);
+
+                Ok(Command::Colors {
+                    channel,
+                    half,
+                    values,
+                })
+            }
+            target => Err(DecodeError::Target(target)),
+        }
+    }
+
+    /// Writes values of [`Command::Colors`] into `colors`, other commands are ignored
+    pub fn apply(&self, colors: &mut [Color; LEDS_COUNT]) {
+        let Command::Colors {
+            channel,
+            half,
+            values,
+        } = self
+        else {
+            return;
+        };
+
+        let first = half.first_led();
+        for (color, &value) in colors[first..].iter_mut().zip(values) {
+            match channel {
+                Channel::Red => color.r = value,
+                Channel::Green => color.g = value,
+                Channel::Blue => color.b = value,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Channel;
+    use super::Command;
+    use super::DecodeError;
+    use super::Half;
+    use super::COLORS_OFFSET;
+    use super::LEDS_COUNT;
+    use super::LEDS_PER_PACKET;
+    use crate::color::Color;
+
+    fn golden(hex: &str) -> Vec<u8> {
+        let mut packet = Vec::from_iter(
+            hex.split_whitespace()
+                .map(|byte| u8::from_str_radix(byte, 16).unwrap()),
+        );
+        packet.resize(64, 0);
+        packet
+    }
+
+    #[test]
+    fn encode() {
+        assert_eq!(
+            Command::PrepareControl.encode().as_slice(),
+            golden("07 03 06 01")
+        );
+        assert_eq!(
+            Command::SetControl { software: true }.encode().as_slice(),
+            golden("07 03 06 00 00 00 00 00 01")
+        );
+        assert_eq!(
+            Command::SetControl { software: false }.encode().as_slice(),
+            golden("07 03 06 00")
+        );
+
+        let mut values = [0u8; 58];
+        values[0] = 0xaa;
+        values[57] = 0xbb;
+        let colors = Command::Colors {
+            channel: Channel::Green,
+            half: Half::Second,
+            values,
+        };
+        let mut expected = golden("07 03 06 0a 00 00 aa");
+        expected[63] = 0xbb;
+        assert_eq!(colors.encode().as_slice(), expected);
+    }
+
+    #[test]
+    fn display() {
+        let names = Command::frame(&[Color::default(); LEDS_COUNT]).map(|cmd| cmd.to_string());
+        assert_eq!(
+            names,
+            [
+                "colors: red 0..58",
+                "colors: red 58..104",
+                "colors: green 0..58",
+                "colors: green 58..104",
+                "colors: blue 0..58",
+                "colors: blue 58..104",
+            ]
+        );
+    }
+
+    #[test]
+    fn targets() {
+        let targets = Command::frame(&[Color::default(); LEDS_COUNT]).map(|cmd| cmd.target());
+        assert_eq!(targets, [0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c]);
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(
+            Command::decode(&golden("07 03 06 01")),
+            Ok(Command::PrepareControl)
+        );
+        assert_eq!(
+            Command::decode(&golden("07 03 06 00 00 00 00 00 01")),
+            Ok(Command::SetControl { software: true })
+        );
+
+        let mut colors = [Color::default(); LEDS_COUNT];
+        for (i, color) in colors.iter_mut().enumerate() {
+            let i = i as u8;
+            *color = Color {
+                r: i,
+                g: !i,
+                b: i ^ 0x55,
+            };
+        }
+
+        let mut decoded = [Color::default(); LEDS_COUNT];
+        for command in Command::frame(&colors) {
+            let packet = command.encode();
+            assert_eq!(Command::decode(&packet).as_ref(), Ok(&command));
+            Command::decode(&packet).unwrap().apply(&mut decoded);
+        }
+        assert_eq!(decoded, colors);
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert_eq!(Command::decode(&[0x07; 8]), Err(DecodeError::Size(8)));
+        assert_eq!(
+            Command::decode(&golden("07 03 05 01")),
+            Err(DecodeError::Header([0x07, 0x03, 0x05]))
+        );
+        assert_eq!(
+            Command::decode(&golden("07 03 06 0d")),
+            Err(DecodeError::Target(0x0d))
+        );
+        assert_eq!(
+            Command::decode(&golden("07 03 06 00 00 00 00 00 02")),
+            Err(DecodeError::Control(0x02))
+        );
+    }
+
+    #[test]
+    fn decode_reserved() {
+        let reserved = |offset, value| Err(DecodeError::Reserved { offset, value });
+
+        assert_eq!(
+            Command::decode(&golden("07 03 06 01 00 00 00 00 01")),
+            reserved(8, 0x01)
+        );
+        assert_eq!(
+            Command::decode(&golden("07 03 06 00 00 00 ff 00 01")),
+            reserved(6, 0xff)
+        );
+        let mut packet = golden("07 03 06 00 00 00 00 00 01");
+        packet[63] = 0x10;
+        assert_eq!(Command::decode(&packet), reserved(63, 0x10));
+
+        assert_eq!(
+            Command::decode(&golden("07 03 06 07 00 01 aa")),
+            reserved(5, 0x01)
+        );
+
+        // the last 12 values of the second half have no keys
+        let mut packet = golden("07 03 06 08 00 00 aa");
+        packet[COLORS_OFFSET + LEDS_COUNT - LEDS_PER_PACKET - 1] = 0xbb;
+        assert!(Command::decode(&packet).is_ok());
+        packet[COLORS_OFFSET + LEDS_COUNT - LEDS_PER_PACKET] = 0xcc;
+        assert_eq!(Command::decode(&packet), reserved(52, 0xcc));
+
+        // but they are values of keys in the first half
+        let mut packet = golden("07 03 06 07 00 00 aa");
+        packet[63] = 0xcc;
+        assert_eq!(
+            Command::decode(&packet).map(|command| command.encode().to_vec()),
+            Ok(packet)
+        );
+    }
+}
@@ -0,0 +1,394 @@
+use super::Driver;
+use crate::color::Color;
+use crate::devices::transport::ReportType;
+use crate::devices::DeviceInfo;
+use crate::devices::Writer;
+use crate::Error;
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Directory where CLI looks up device definitions by default
+pub const DEFAULT_DEFINITIONS_DIR: &str = "/etc/a4keyboard/devices";
+
+#[derive(Debug, thiserror::Error)]
+pub enum DefinitionError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Parse(#[from] toml::de::Error),
+    #[error("at least one `[[match]]` rule is required")]
+    NoMatchRules,
+    #[error("`{field}` doesn't fit into report of {report_size} bytes")]
+    TooLong {
+        field: &'static str,
+        report_size: usize,
+    },
+    #[error("`red`, `green` and `blue` must have the same non-zero number of targets")]
+    ChannelsMismatch,
+    #[error("{packets} packets of {leds_per_packet} LEDs can't hold all keys")]
+    NotEnoughLeds {
+        packets: usize,
+        leds_per_packet: usize,
+    },
+}
+
+/// Keyboard driver described by TOML file instead of code
+///
+/// ```toml
+/// name = "bloody-b820r"
+/// report_size = 64
+/// header = [0x07, 0x03, 0x06] # first bytes of every report
+/// report_type = "feature" # or "output" and "output-interrupt"
+///
+/// [[match]]
+/// vid = 0x09da
+/// pid = 0xfa10
+/// usage_page = 0xff52 # optional, checked against report descriptor
+/// usage = 0x0210
+///
+/// [control] # reports which follow header
+/// gain = [[0x01], [0x00, 0x00, 0x00, 0x00, 0x00, 0x01]]
+/// release = [[0x01], [0x00]]
+///
+/// [colors] # one report per target, colors are placed at `offset` of report
+/// offset = 6
+/// leds_per_packet = 58
+/// red = [0x07, 0x08]
+/// green = [0x09, 0x0a]
+/// blue = [0x0b, 0x0c]
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Definition {
+    name: String,
+    report_size: usize,
+    #[serde(default)]
+    header: Vec<u8>,
+    #[serde(default)]
+    report_type: ReportType,
+    #[serde(rename = "match")]
+    rules: Vec<MatchRule>,
+    control: Control,
+    colors: Colors,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MatchRule {
+    vid: u16,
+    pid: u16,
+    usage_page: Option<u32>,
+    usage: Option<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Control {
+    gain: Vec<Vec<u8>>,
+    release: Vec<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Colors {
+    offset: usize,
+    leds_per_packet: usize,
+    red: Vec<u8>,
+    green: Vec<u8>,
+    blue: Vec<u8>,
+}
+
+impl MatchRule {
+    fn matches(&self, info: &DeviceInfo) -> bool {
+        if info.vid != self.vid || info.pid != self.pid {
+            return false;
+        }
+
+        if self.usage_page.is_none() && self.usage.is_none() {
+            return true;
+        }
+
+        info.report_descriptor.iter().any(|report| {
+            self.usage_page.is_none_or(|page| report.usage_page == page)
+                && self.usage.is_none_or(|usage| report.usage == usage)
+        })
+    }
+}
+
+impl FromStr for Definition {
+    type Err = DefinitionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let definition: Self = toml::from_str(s)?;
+        definition.validate()?;
+
+        Ok(definition)
+    }
+}
+
+impl Definition {
+    /// Reads and checks definition file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let to_error = |source| Error::Definition {
+            path: path.to_owned(),
+            source,
+        };
+
+        let content = fs::read_to_string(path).map_err(|err| to_error(err.into()))?;
+        content.parse().map_err(to_error)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn validate(&self) -> Result<(), DefinitionError> {
+        let report_size = self.report_size;
+        let too_long = |field| DefinitionError::TooLong { field, report_size };
+
+        if self.rules.is_empty() {
+            return Err(DefinitionError::NoMatchRules);
+        }
+
+        let control = self.control.gain.iter().chain(&self.control.release);
+        for body in control {
+            if self.header.len() + body.len() > report_size {
+                return Err(too_long("control"));
+            }
+        }
+
+        let colors = &self.colors;
+        if self.header.len() + 1 > colors.offset {
+            return Err(too_long("header"));
+        }
+        if colors.offset + colors.leds_per_packet > report_size {
+            return Err(too_long("leds_per_packet"));
+        }
+
+        let packets = colors.red.len();
+        if packets == 0 || colors.green.len() != packets || colors.blue.len() != packets {
+            return Err(DefinitionError::ChannelsMismatch);
+        }
+        if packets * colors.leds_per_packet < 104 {
+            return Err(DefinitionError::NotEnoughLeds {
+                packets,
+                leds_per_packet: colors.leds_per_packet,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn report(&self, body: &[u8]) -> Vec<u8> {
+        let mut report = vec![0u8; self.report_size];
+        report[..self.header.len()].copy_from_slice(&self.header);
+        report[self.header.len()..][..body.len()].copy_from_slice(body);
+        report
+    }
+
+    fn write_all(&self, writer: &mut Writer, bodies: &[Vec<u8>]) -> Result<(), Error> {
+        for body in bodies {
+            writer.write_report(self.report_type, &self.report(body))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Driver for Definition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn probe(&self, info: &DeviceInfo) -> bool {
+        self.rules.iter().any(|rule| rule.matches(info))
+    }
+
+    fn gain_control(&self, writer: &mut Writer) -> Result<(), Error> {
+        self.write_all(writer, &self.control.gain)
+    }
+
+    fn release_control(&self, writer: &mut Writer) -> Result<(), Error> {
+        self.write_all(writer, &self.control.release)
+    }
+
+    fn set_colors(&self, writer: &mut Writer, colors: &[Color; 104]) -> Result<(), Error> {
+        let Colors {
+            offset,
+            leds_per_packet,
+            ref red,
+            ref green,
+            ref blue,
+        } = self.colors;
+
+        type Component = fn(&Color) -> u8;
+
+        let channels: [(&[u8], Component); 3] = [
+            (red, |color| color.r),
+            (green, |color| color.g),
+            (blue, |color| color.b),
+        ];
+
+        let mut reports = Vec::new();
+        for (targets, channel) in channels {
+            for (&target, colors) in targets.iter().zip(colors.chunks(leds_per_packet)) {
+                let mut report = self.report(&[target]);
+                for (byte, color) in report[offset..].iter_mut().zip(colors) {
+                    *byte = channel(color);
+                }
+
+                reports.push(report);
+            }
+        }
+
+        let reports = Vec::from_iter(reports.iter().map(Vec::as_slice));
+        writer.write_batch(self.report_type, &reports)
+    }
+}
+
+/// Loads every `*.toml` file from `dir` sorted by file name
+pub(crate) fn load_dir(dir: &Path) -> Result<Vec<Definition>, Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(source) => {
+            return Err(Error::Enumeration {
+                path: dir.to_owned(),
+                source,
+            })
+        }
+    };
+
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    paths.iter().map(Definition::load).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::Definition;
+    use super::DefinitionError;
+    use crate::color::Color;
+    use crate::devices::testing::Harness;
+    use crate::devices::transport::ReportType;
+    use crate::devices::DeviceInfo;
+
+    const BLOODY: &str = include_str!("../../definitions/bloody-b820r.toml");
+
+    // vendor collection of Bloody B820R
+    const REPORT_DESCRIPTOR: &[u8] = &[
+        0x06, 0x52, 0xff, 0x0a, 0x10, 0x02, 0xa1, 0x01, 0x85, 0x07, 0x19, 0x01, 0x29, 0x3f, 0x15,
+        0x00, 0x26, 0xff, 0x00, 0x75, 0x08, 0x95, 0x3f, 0x81, 0x00, 0x19, 0x01, 0x29, 0x3f, 0x15,
+        0x00, 0x26, 0xff, 0x00, 0x75, 0x08, 0x95, 0x3f, 0xb1, 0x02, 0xc0,
+    ];
+
+    fn info() -> DeviceInfo {
+        DeviceInfo::new(5, 0x09da, 0xfa10, hrd::parse(REPORT_DESCRIPTOR).unwrap())
+    }
+
+    #[test]
+    fn same_as_builtin() {
+        let definition: Definition = BLOODY.parse().unwrap();
+        assert_eq!(definition.name(), "bloody-b820r");
+
+        let mut defined = Harness::probe_definition(definition, &info()).unwrap();
+        let mut builtin = Harness::probe(&info()).unwrap();
+
+        let mut colors = [Color::default(); 104];
+        for (i, color) in colors.iter_mut().enumerate() {
+            let i = i as u8;
+            *color = Color {
+                r: i,
+                g: i.wrapping_mul(3),
+                b: !i,
+            };
+        }
+
+        assert_eq!(
+            defined.gain_control().unwrap(),
+            builtin.gain_control().unwrap()
+        );
+        assert_eq!(
+            defined.set_colors(&colors).unwrap(),
+            builtin.set_colors(&colors).unwrap()
+        );
+        assert_eq!(
+            defined.release_control().unwrap(),
+            builtin.release_control().unwrap()
+        );
+    }
+
+    #[test]
+    fn probe() {
+        let definition: Definition = BLOODY.parse().unwrap();
+
+        let info = DeviceInfo::new(5, 0x09da, 0xfa10, hrd::parse(&[]).unwrap());
+        assert!(Harness::probe_definition(definition, &info).is_none());
+    }
+
+    #[test]
+    fn report_type() {
+        let output = BLOODY.replace(
+            "header = [0x07, 0x03, 0x06]",
+            "header = [0x07, 0x03, 0x06]\nreport_type = \"output-interrupt\"",
+        );
+        let definition: Definition = output.parse().unwrap();
+
+        let mut harness = Harness::probe_definition(definition, &info()).unwrap();
+        let packets = harness.set_colors(&[Color::default(); 104]).unwrap();
+        assert_eq!(packets.len(), 6);
+        assert!(packets
+            .iter()
+            .all(|packet| packet.report_type == ReportType::OutputInterrupt));
+
+        let broken = BLOODY.replace(
+            "header = [0x07, 0x03, 0x06]",
+            "header = [0x07, 0x03, 0x06]\nreport_type = \"input\"",
+        );
+        assert!(matches!(
+            broken.parse::<Definition>(),
+            Err(DefinitionError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn validate() {
+        let broken = BLOODY.replace("leds_per_packet = 58", "leds_per_packet = 50");
+        assert!(matches!(
+            broken.parse::<Definition>(),
+            Err(DefinitionError::NotEnoughLeds { .. })
+        ));
+
+        let broken = BLOODY.replace("offset = 6", "offset = 10");
+        assert!(matches!(
+            broken.parse::<Definition>(),
+            Err(DefinitionError::TooLong {
+                field: "leds_per_packet",
+                ..
+            })
+        ));
+
+        let broken = BLOODY.replace("blue = [0x0b, 0x0c]", "blue = [0x0b]");
+        assert!(matches!(
+            broken.parse::<Definition>(),
+            Err(DefinitionError::ChannelsMismatch)
+        ));
+
+        let broken = BLOODY.replace("[[match]]", "[[matches]]");
+        assert!(matches!(
+            broken.parse::<Definition>(),
+            Err(DefinitionError::Parse(_))
+        ));
+    }
+}
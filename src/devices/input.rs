@@ -0,0 +1,389 @@
+//! Key presses decoded from input reports
+//!
+//! Reports are read by [`BpfInput`] inside the kernel, so they are available even where
+//! compositor doesn't let other clients see keyboard, like under Wayland.
+
+use std::collections::HashMap;
+
+pub mod bpf;
+
+pub use bpf::BpfInput;
+
+/// Usage pages with keys, other fields like vendor data and axes are ignored
+const USAGE_PAGE_GENERIC_DESKTOP: u32 = 0x01;
+const USAGE_PAGE_KEYBOARD: u32 = 0x07;
+const USAGE_PAGE_CONSUMER: u32 = 0x0C;
+
+/// Every array field is set to this usage when too many keys are pressed
+const USAGE_ERROR_ROLL_OVER: u16 = 0x01;
+
+/// Input report read from device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputReport {
+    pub hid: u16,
+    /// Report with its ID if device uses report IDs
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub usage_page: u16,
+    /// Usage ID, the same as [`Key::usage`](crate::layout::Key::usage) on Keyboard/Keypad page
+    pub usage: u16,
+    pub pressed: bool,
+}
+
+/// Main item of report descriptor which reports keys
+#[derive(Debug, Clone)]
+struct Field {
+    report_id: Option<u8>,
+    usage_page: u16,
+    /// Usages of fields or indices, the last one is repeated for the rest of fields
+    usages: Vec<u32>,
+    usage_minimum: u32,
+    usage_maximum: u32,
+    logical_minimum: i32,
+    bit_offset: u32,
+    size: u32,
+    count: u32,
+    /// Bitmap of keys, otherwise array of usage indices
+    variable: bool,
+}
+
+impl Field {
+    fn new(report: &hrd::Report, has_report_ids: bool) -> Option<Self> {
+        let key_page = matches!(
+            report.usage_page,
+            USAGE_PAGE_GENERIC_DESKTOP | USAGE_PAGE_KEYBOARD | USAGE_PAGE_CONSUMER
+        );
+        // only 1-bit variable fields are buttons
+        let keys = report.is_variable() && report.report_size == 1
+            || !report.is_variable() && (1..=32).contains(&report.report_size);
+        if report.r#type != hrd::ReportType::Input || report.is_constant() || !key_page || !keys {
+            return None;
+        }
+
+        // fields without usages, usage of collection isn't usage of key
+        let (usage_minimum, usage_maximum) = match (report.usage_minimum, report.usage_maximum) {
+            (Some(minimum), Some(maximum)) => (minimum, maximum),
+            _ if !report.usages.is_empty() => (0, 0),
+            _ => return None,
+        };
+
+        Some(Self {
+            report_id: has_report_ids.then_some(report.report_id as u8),
+            usage_page: report.usage_page as u16,
+            usages: report.usages.clone(),
+            usage_minimum,
+            usage_maximum,
+            logical_minimum: report.logical_minimum,
+            bit_offset: report.bit_offset,
+            size: report.report_size,
+            count: report.report_count,
+            variable: report.is_variable(),
+        })
+    }
+
+    /// Returns usage at `index` of usage list or range
+    fn usage(&self, index: u32) -> Option<u32> {
+        if let Some(last) = self.usages.last() {
+            // only variable fields repeat the last usage, other indices of arrays are invalid
+            return match self.usages.get(index as usize) {
+                Some(usage) => Some(*usage),
+                None => self.variable.then_some(*last),
+            };
+        }
+
+        let usage = self.usage_minimum.checked_add(index)?;
+        (usage <= self.usage_maximum).then_some(usage)
+    }
+
+    /// Adds usages of pressed keys, returns `false` if keyboard reports roll over error
+    fn pressed(&self, data: &[u8], keys: &mut Vec<(u16, u16)>) -> bool {
+        for i in 0..self.count {
+            let offset = i
+                .checked_mul(self.size)
+                .and_then(|offset| offset.checked_add(self.bit_offset));
+            let Some(value) = offset.and_then(|offset| bits(data, offset, self.size)) else {
+                break;
+            };
+
+            let usage = if self.variable {
+                if value == 0 {
+                    continue;
+                }
+                self.usage(i)
+            } else {
+                let index = value as i64 - self.logical_minimum as i64;
+                if index < 0 || index > u32::MAX as i64 {
+                    continue;
+                }
+                self.usage(index as u32)
+            };
+
+            // usage 0 means no key in arrays
+            let Some(usage) = usage.filter(|&usage| usage != 0) else {
+                continue;
+            };
+            // extended usage has its own page in high bytes
+            let usage_page = match (usage >> 16) as u16 {
+                0 => self.usage_page,
+                usage_page => usage_page,
+            };
+            let usage = usage as u16;
+
+            if !self.variable
+                && usage_page as u32 == USAGE_PAGE_KEYBOARD
+                && usage == USAGE_ERROR_ROLL_OVER
+            {
+                return false;
+            }
+
+            let key = (usage_page, usage);
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+
+        true
+    }
+}
+
+/// Reads `size` bits at `offset`, bits are in little-endian order
+fn bits(data: &[u8], offset: u32, size: u32) -> Option<u32> {
+    let mut value = 0u64;
+    let first = (offset / 8) as usize;
+    let last = offset.checked_add(size)?.div_ceil(8) as usize;
+    for (i, byte) in data.get(first..last)?.iter().enumerate() {
+        value |= (*byte as u64) << (i * 8);
+    }
+
+    let value = value >> (offset % 8);
+    Some((value & ((1u64 << size) - 1)) as u32)
+}
+
+/// Turns input reports into press and release events of keys
+///
+/// Bitmap and array fields on Keyboard/Keypad, Consumer and Generic Desktop pages are
+/// decoded, reports are compared with the previous report with the same ID.
+#[derive(Debug, Clone)]
+pub struct KeyDecoder {
+    has_report_ids: bool,
+    fields: Vec<Field>,
+    pressed: HashMap<Option<u8>, Vec<(u16, u16)>>,
+}
+
+impl KeyDecoder {
+    pub fn new(descriptor: &hrd::Descriptor) -> Self {
+        let has_report_ids = descriptor.has_report_ids;
+
+        Self {
+            has_report_ids,
+            fields: Vec::from_iter(
+                descriptor
+                    .iter()
+                    .filter_map(|report| Field::new(report, has_report_ids)),
+            ),
+            pressed: HashMap::new(),
+        }
+    }
+
+    /// Returns `false` if device doesn't report keys
+    pub fn has_keys(&self) -> bool {
+        !self.fields.is_empty()
+    }
+
+    /// Returns releases and then presses since previous report with the same ID
+    pub fn decode(&mut self, report: &[u8]) -> Vec<KeyEvent> {
+        let (report_id, data) = match (self.has_report_ids, report) {
+            (true, [id, data @ ..]) => (Some(*id), data),
+            (true, []) => return Vec::new(),
+            (false, data) => (None, data),
+        };
+
+        let mut fields = self
+            .fields
+            .iter()
+            .filter(|field| field.report_id == report_id)
+            .peekable();
+        if fields.peek().is_none() {
+            return Vec::new();
+        }
+
+        let mut pressed = Vec::new();
+        for field in fields {
+            // keys stay pressed until keyboard reports them again
+            if !field.pressed(data, &mut pressed) {
+                return Vec::new();
+            }
+        }
+
+        let previous = self.pressed.insert(report_id, pressed.clone());
+        let previous = previous.unwrap_or_default();
+
+        let released = previous
+            .iter()
+            .filter(|key| !pressed.contains(key))
+            .map(|&key| (key, false));
+        let pressed = pressed
+            .iter()
+            .filter(|key| !previous.contains(key))
+            .map(|&key| (key, true));
+
+        Vec::from_iter(
+            released
+                .chain(pressed)
+                .map(|((usage_page, usage), pressed)| KeyEvent {
+                    usage_page,
+                    usage,
+                    pressed,
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::bits;
+    use super::KeyDecoder;
+    use super::KeyEvent;
+
+    /// Boot keyboard from HID specification, modifiers bitmap and six keys array
+    const BOOT_KEYBOARD: &[u8] = &[
+        0x05, 0x01, 0x09, 0x06, 0xa1, 0x01, 0x05, 0x07, 0x19, 0xe0, 0x29, 0xe7, 0x15, 0x00, 0x25,
+        0x01, 0x75, 0x01, 0x95, 0x08, 0x81, 0x02, 0x95, 0x01, 0x75, 0x08, 0x81, 0x01, 0x95, 0x06,
+        0x75, 0x08, 0x15, 0x00, 0x25, 0x65, 0x05, 0x07, 0x19, 0x00, 0x29, 0x65, 0x81, 0x00, 0xc0,
+    ];
+
+    const BLOODY: &[u8] = include_bytes!(
+        "../../tests/fixtures/sysfs/bus/hid/devices/0003:09DA:FA10.0005/report_descriptor"
+    );
+
+    fn key(usage_page: u16, usage: u16, pressed: bool) -> KeyEvent {
+        KeyEvent {
+            usage_page,
+            usage,
+            pressed,
+        }
+    }
+
+    #[test]
+    fn read_bits() {
+        let data = [0b1010_0101, 0b0011_1100];
+        assert_eq!(bits(&data, 0, 1), Some(1));
+        assert_eq!(bits(&data, 1, 1), Some(0));
+        assert_eq!(bits(&data, 4, 8), Some(0b1100_1010));
+        assert_eq!(bits(&data, 0, 16), Some(0x3ca5));
+        assert_eq!(bits(&data, 12, 8), None);
+        assert_eq!(bits(&data, u32::MAX, 8), None);
+    }
+
+    #[test]
+    fn boot_keyboard() {
+        let mut decoder = KeyDecoder::new(&hrd::parse(BOOT_KEYBOARD).unwrap());
+        assert!(decoder.has_keys());
+
+        // left shift and `a`
+        assert_eq!(
+            decoder.decode(&[0x02, 0, 0x04, 0, 0, 0, 0, 0]),
+            [key(0x07, 0xe1, true), key(0x07, 0x04, true)]
+        );
+        // `b` is added to another slot
+        assert_eq!(
+            decoder.decode(&[0x02, 0, 0x04, 0x05, 0, 0, 0, 0]),
+            [key(0x07, 0x05, true)]
+        );
+        // the same keys in another order
+        assert_eq!(decoder.decode(&[0x02, 0, 0x05, 0x04, 0, 0, 0, 0]), []);
+        // roll over error doesn't release keys
+        assert_eq!(decoder.decode(&[0x02, 0, 1, 1, 1, 1, 1, 1]), []);
+        assert_eq!(
+            decoder.decode(&[0, 0, 0x05, 0, 0, 0, 0, 0]),
+            [key(0x07, 0xe1, false), key(0x07, 0x04, false)]
+        );
+        assert_eq!(decoder.decode(&[0; 8]), [key(0x07, 0x05, false)]);
+    }
+
+    #[test]
+    fn usage_list() {
+        // mute and volume up buttons with their own usages, then bits without usages
+        let mut decoder = KeyDecoder::new(
+            &hrd::parse(&[
+                0x05, 0x0c, 0x09, 0x01, 0xa1, 0x01, 0x09, 0xe2, 0x09, 0xe9, 0x15, 0x00, 0x25, 0x01,
+                0x75, 0x01, 0x95, 0x02, 0x81, 0x02, 0x95, 0x06, 0x81, 0x02, 0xc0,
+            ])
+            .unwrap(),
+        );
+
+        assert_eq!(decoder.decode(&[0b10]), [key(0x0c, 0xe9, true)]);
+        // bits without usages aren't keys
+        assert_eq!(
+            decoder.decode(&[0b1111_1101]),
+            [key(0x0c, 0xe9, false), key(0x0c, 0xe2, true)]
+        );
+    }
+
+    #[test]
+    fn array_usage_list() {
+        // array of one key from the list, the second usage is extended usage of keyboard page
+        let mut decoder = KeyDecoder::new(
+            &hrd::parse(&[
+                0x05, 0x0c, 0x09, 0x01, 0xa1, 0x01, 0x09, 0xe2, 0x0b, 0x04, 0x00, 0x07, 0x00, 0x15,
+                0x00, 0x25, 0x03, 0x75, 0x08, 0x95, 0x01, 0x81, 0x00, 0xc0,
+            ])
+            .unwrap(),
+        );
+
+        assert_eq!(decoder.decode(&[0]), [key(0x0c, 0xe2, true)]);
+        assert_eq!(
+            decoder.decode(&[1]),
+            [key(0x0c, 0xe2, false), key(0x07, 0x04, true)]
+        );
+        // indices past the list aren't keys
+        assert_eq!(decoder.decode(&[3]), [key(0x07, 0x04, false)]);
+    }
+
+    #[test]
+    fn oversized_field() {
+        let mut decoder = KeyDecoder::new(&hrd::parse(BOOT_KEYBOARD).unwrap());
+        decoder.fields[0].bit_offset = u32::MAX;
+        decoder.fields[0].count = u32::MAX;
+
+        // field doesn't fit into report, other fields are decoded
+        assert_eq!(
+            decoder.decode(&[0xff, 0, 0x04, 0, 0, 0, 0, 0]),
+            [key(0x07, 0x04, true)]
+        );
+    }
+
+    #[test]
+    fn bloody() {
+        let mut decoder = KeyDecoder::new(&hrd::parse(BLOODY).unwrap());
+
+        // report 1 is bitmap of modifiers and 152 keys after them
+        let mut report = [0u8; 21];
+        report[0] = 0x01;
+        report[1] = 0x01; // left control
+        report[2] = 1 << 4; // `a`, usages of keys start at 0
+        assert_eq!(
+            decoder.decode(&report),
+            [key(0x07, 0xe0, true), key(0x07, 0x04, true)]
+        );
+
+        // consumer control is 16-bit array with its own report ID
+        assert_eq!(decoder.decode(&[0x03, 0xe9, 0x00]), [key(0x0c, 0xe9, true)]);
+        assert_eq!(
+            decoder.decode(&[0x03, 0x00, 0x00]),
+            [key(0x0c, 0xe9, false)]
+        );
+
+        // keys of report 1 are still pressed
+        report[1] = 0;
+        assert_eq!(decoder.decode(&report), [key(0x07, 0xe0, false)]);
+
+        // vendor report isn't decoded
+        let mut vendor = [0xffu8; 64];
+        vendor[0] = 0x07;
+        assert_eq!(decoder.decode(&vendor), []);
+    }
+}
@@ -0,0 +1,88 @@
+use super::InputReport;
+use crate::devices::input_bpf;
+use crate::Error;
+use libbpf_rs::skel::OpenSkel as _;
+use libbpf_rs::skel::SkelBuilder as _;
+use libbpf_rs::Link;
+use libbpf_rs::MapHandle;
+use libbpf_rs::RingBuffer;
+use libbpf_rs::RingBufferBuilder;
+use std::mem::MaybeUninit;
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// Size of report in ring buffer sample, followed by its data, the same as in `input.bpf.c`
+const SIZE_LEN: usize = 4;
+
+/// Reads input reports through `input.bpf.c` which is attached to `hid_device_event` of
+/// every keyboard
+///
+/// Needs Linux 6.11 or newer for HID-BPF struct_ops and `CAP_BPF`. Reports are copied,
+/// keyboards keep working as usual.
+pub struct BpfInput {
+    ring: RingBuffer<'static>,
+    reports: mpsc::Receiver<InputReport>,
+    // ring buffer polls descriptors of maps, programs are detached when links are dropped
+    _maps: Vec<MapHandle>,
+    _links: Vec<Link>,
+}
+
+impl BpfInput {
+    /// Loads one copy of program for every device in `hids`
+    pub fn attach(hids: &[u16]) -> Result<Self, Error> {
+        let (sender, reports) = mpsc::channel();
+        let mut maps = Vec::new();
+        let mut links = Vec::new();
+
+        for &hid in hids {
+            let mut object = MaybeUninit::uninit();
+            let mut program = input_bpf::InputSkelBuilder::default().open(&mut object)?;
+            program.struct_ops.input_ops_mut().hid_id = hid as i32;
+
+            let mut program = program.load()?;
+            links.push(program.maps.input_ops.attach_struct_ops()?);
+            maps.push(MapHandle::try_from(&program.maps.events)?);
+        }
+
+        let mut builder = RingBufferBuilder::new();
+        for (&hid, map) in hids.iter().zip(&maps) {
+            let sender = sender.clone();
+            builder.add(map, move |sample: &[u8]| {
+                let Some((size, data)) = sample.split_first_chunk::<SIZE_LEN>() else {
+                    return 0;
+                };
+                let size = (u32::from_ne_bytes(*size) as usize).min(data.len());
+
+                // receiver is dropped only with ring buffer
+                let _ = sender.send(InputReport {
+                    hid,
+                    data: data[..size].to_vec(),
+                });
+                0
+            })?;
+        }
+
+        Ok(Self {
+            ring: builder.build()?,
+            reports,
+            _maps: maps,
+            _links: links,
+        })
+    }
+
+    /// Waits up to `timeout` for reports, returns all reports which arrived since previous
+    /// call in order of arrival
+    pub fn read(&mut self, timeout: Duration) -> Result<Vec<InputReport>, Error> {
+        let mut reports = Vec::from_iter(self.reports.try_iter());
+        if reports.is_empty() {
+            match self.ring.poll(timeout) {
+                // signal, caller checks whether it should stop
+                Err(err) if err.kind() == libbpf_rs::ErrorKind::Interrupted => {}
+                result => result?,
+            }
+            reports.extend(self.reports.try_iter());
+        }
+
+        Ok(reports)
+    }
+}
@@ -0,0 +1,93 @@
+use crate::devices::from_hex;
+use crate::devices::DeviceInfo;
+use std::fmt;
+use std::str::FromStr;
+
+/// Selects devices which commands are applied to
+///
+/// Parsed from `hid:0005`, `id:09da:fa10`, `sysfs:0003:09DA:FA10.0005` or `uniq:SERIAL`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceSelector {
+    Hid(u16),
+    VidPid(u16, u16),
+    SysfsName(String),
+    Uniq(String),
+}
+
+impl DeviceSelector {
+    pub fn matches(&self, info: &DeviceInfo) -> bool {
+        match self {
+            DeviceSelector::Hid(hid) => info.hid == *hid,
+            DeviceSelector::VidPid(vid, pid) => info.vid == *vid && info.pid == *pid,
+            DeviceSelector::SysfsName(name) => info.sysfs_name.eq_ignore_ascii_case(name),
+            DeviceSelector::Uniq(uniq) => info.uniq.as_deref() == Some(uniq.as_str()),
+        }
+    }
+}
+
+fn parse_hex(value: &str) -> Result<u16, String> {
+    from_hex(value.as_bytes()).ok_or_else(|| format!("incorrect hex value `{value}`"))
+}
+
+impl FromStr for DeviceSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((kind, value)) = s.split_once(':') else {
+            return Err(format!(
+                "incorrect selector `{s}`, expected `hid:`, `id:`, `sysfs:` or `uniq:` prefix"
+            ));
+        };
+
+        match kind {
+            "hid" => Ok(DeviceSelector::Hid(parse_hex(value)?)),
+            "id" => {
+                let (vid, pid) = value
+                    .split_once(':')
+                    .ok_or_else(|| format!("incorrect id `{value}`, expected `VID:PID`"))?;
+                Ok(DeviceSelector::VidPid(parse_hex(vid)?, parse_hex(pid)?))
+            }
+            "sysfs" => Ok(DeviceSelector::SysfsName(value.to_owned())),
+            "uniq" => Ok(DeviceSelector::Uniq(value.to_owned())),
+            _ => Err(format!("unknown selector kind `{kind}`")),
+        }
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceSelector::Hid(hid) => write!(f, "hid:{hid:04x}"),
+            DeviceSelector::VidPid(vid, pid) => write!(f, "id:{vid:04x}:{pid:04x}"),
+            DeviceSelector::SysfsName(name) => write!(f, "sysfs:{name}"),
+            DeviceSelector::Uniq(uniq) => write!(f, "uniq:{uniq}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DeviceSelector;
+
+    #[test]
+    fn parse() {
+        assert_eq!("hid:0005".parse(), Ok(DeviceSelector::Hid(0x0005)));
+        assert_eq!(
+            "id:09da:FA10".parse(),
+            Ok(DeviceSelector::VidPid(0x09da, 0xfa10))
+        );
+        assert_eq!(
+            "sysfs:0003:09DA:FA10.0005".parse(),
+            Ok(DeviceSelector::SysfsName("0003:09DA:FA10.0005".to_owned()))
+        );
+        assert_eq!(
+            "uniq:8A4F:0C21".parse(),
+            Ok(DeviceSelector::Uniq("8A4F:0C21".to_owned()))
+        );
+
+        assert!("0005".parse::<DeviceSelector>().is_err());
+        assert!("hid:xyz".parse::<DeviceSelector>().is_err());
+        assert!("id:09da".parse::<DeviceSelector>().is_err());
+        assert!("serial:1".parse::<DeviceSelector>().is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use crate::color::Color;
+use crate::devices::definition::Definition;
+use crate::devices::transport::Packet;
+use crate::devices::transport::RecordingTransport;
+use crate::devices::Device;
+use crate::devices::DeviceFunctions;
+use crate::devices::DeviceHandle;
+use crate::devices::DeviceInfo;
+use crate::devices::Devices;
+use crate::devices::Driver;
+use crate::devices::Writer;
+use crate::Error;
+use std::sync::Arc;
+
+pub mod uhid;
+
+/// Runs registered driver against [`RecordingTransport`]
+///
+/// Allows to check packets which driver sends without root and real device.
+pub struct Harness {
+    driver: Arc<dyn Driver>,
+    transport: RecordingTransport,
+    hid: u16,
+}
+
+impl Harness {
+    /// Creates harness for first built-in driver which supports `info`
+    pub fn probe(info: &DeviceInfo) -> Option<Self> {
+        let driver = Arc::clone(Devices::new().find_driver(info)?);
+
+        Some(Self::with_driver(driver, info))
+    }
+
+    /// Creates harness for driver `D` if it supports `info`
+    pub fn probe_driver<D: Device>(info: &DeviceInfo) -> Option<Self> {
+        D::probe(info).then(|| Self::with_driver(Arc::new(DeviceFunctions::of::<D>()), info))
+    }
+
+    /// Creates harness for data-driven driver if it supports `info`
+    pub fn probe_definition(definition: Definition, info: &DeviceInfo) -> Option<Self> {
+        Driver::probe(&definition, info).then(|| Self::with_driver(Arc::new(definition), info))
+    }
+
+    fn with_driver(driver: Arc<dyn Driver>, info: &DeviceInfo) -> Self {
+        Self {
+            driver,
+            transport: RecordingTransport::new(),
+            hid: info.hid,
+        }
+    }
+
+    pub fn handle(&mut self) -> DeviceHandle<'_> {
+        DeviceHandle {
+            driver: self.driver.as_ref(),
+            writer: Writer {
+                transport: &mut self.transport,
+                hid: self.hid,
+            },
+        }
+    }
+
+    pub fn gain_control(&mut self) -> Result<Vec<Packet>, Error> {
+        self.handle().gain_control()?;
+        Ok(self.transport.take_packets())
+    }
+
+    pub fn release_control(&mut self) -> Result<Vec<Packet>, Error> {
+        self.handle().release_control()?;
+        Ok(self.transport.take_packets())
+    }
+
+    pub fn set_colors(&mut self, colors: &[Color; 104]) -> Result<Vec<Packet>, Error> {
+        self.handle().set_colors(colors)?;
+        Ok(self.transport.take_packets())
+    }
+
+    /// Returns packets sent through [`Harness::handle`] and clears the record
+    pub fn take_packets(&self) -> Vec<Packet> {
+        self.transport.take_packets()
+    }
+}
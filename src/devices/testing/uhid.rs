@@ -0,0 +1,317 @@
+//! Virtual HID devices created through `/dev/uhid`
+//!
+//! Unlike [`super::Harness`] reports go through the kernel, so enumeration in sysfs, hidraw
+//! and HID-BPF are exercised too. Requires root and `uhid` module.
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read as _;
+use std::os::fd::AsRawFd as _;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Report descriptor of Bloody B820R, the same as in `hrd` tests
+pub const BLOODY_REPORT_DESCRIPTOR: &[u8] = include_bytes!(
+    "../../../tests/fixtures/sysfs/bus/hid/devices/0003:09DA:FA10.0005/report_descriptor"
+);
+
+// from `linux/uhid.h`
+const UHID_DESTROY: u32 = 1;
+const UHID_OUTPUT: u32 = 6;
+const UHID_GET_REPORT: u32 = 9;
+const UHID_GET_REPORT_REPLY: u32 = 10;
+const UHID_CREATE2: u32 = 11;
+const UHID_SET_REPORT: u32 = 13;
+const UHID_SET_REPORT_REPLY: u32 = 14;
+
+const UHID_DATA_MAX: usize = 4096;
+/// Size of packed `struct uhid_event`, its largest member is `uhid_create2_req`
+const UHID_EVENT_SIZE: usize = 4 + 128 + 64 + 64 + 2 + 2 + 4 * 4 + UHID_DATA_MAX;
+
+const BUS_USB: u16 = 0x03;
+
+/// `enum uhid_report_type`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportType {
+    Feature,
+    Output,
+    Input,
+}
+
+impl ReportType {
+    fn from_raw(value: u8) -> Self {
+        match value {
+            0 => ReportType::Feature,
+            1 => ReportType::Output,
+            _ => ReportType::Input,
+        }
+    }
+}
+
+/// Report which host sent to virtual device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub r#type: ReportType,
+    /// Report ID is the first byte if device uses numbered reports
+    pub data: Vec<u8>,
+}
+
+/// Keyboard which exists while value is alive and records reports sent by host
+pub struct VirtualKeyboard {
+    uhid: Arc<File>,
+    uniq: String,
+    reports: Arc<Mutex<Vec<Report>>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl VirtualKeyboard {
+    /// Creates Bloody B820R (`09da:fa10`) with serial number `uniq`
+    pub fn bloody(uniq: &str) -> io::Result<Self> {
+        Self::create(
+            "A4TECH USB Device",
+            uniq,
+            0x09da,
+            0xfa10,
+            BLOODY_REPORT_DESCRIPTOR,
+        )
+    }
+
+    pub fn create(
+        name: &str,
+        uniq: &str,
+        vid: u16,
+        pid: u16,
+        report_descriptor: &[u8],
+    ) -> io::Result<Self> {
+        if report_descriptor.len() > UHID_DATA_MAX {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "report descriptor is too big",
+            ));
+        }
+
+        let mut uhid = File::options().read(true).write(true).open("/dev/uhid")?;
+
+        let mut event = Event::new(UHID_CREATE2);
+        event.put_str(0, 128, name);
+        event.put_str(128, 64, "a4keyboard-uhid");
+        event.put_str(192, 64, uniq);
+        event.put(256, &(report_descriptor.len() as u16).to_ne_bytes());
+        event.put(258, &BUS_USB.to_ne_bytes());
+        event.put(260, &u32::from(vid).to_ne_bytes());
+        event.put(264, &u32::from(pid).to_ne_bytes());
+        event.put(276, report_descriptor);
+        event.send(&mut uhid)?;
+
+        let uhid = Arc::new(uhid);
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = thread::spawn({
+            let uhid = Arc::clone(&uhid);
+            let reports = Arc::clone(&reports);
+            let stop = Arc::clone(&stop);
+            move || {
+                if let Err(err) = serve(&uhid, &reports, &stop) {
+                    log::error!("uhid: {err}");
+                }
+            }
+        });
+
+        Ok(Self {
+            uhid,
+            uniq: uniq.to_owned(),
+            reports,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// `HID_UNIQ` of device, can be used in `uniq:` selector
+    pub fn uniq(&self) -> &str {
+        &self.uniq
+    }
+
+    /// Waits until kernel creates device and returns its directory in `/sys/bus/hid/devices`
+    pub fn wait_sysfs(&self, timeout: Duration) -> io::Result<PathBuf> {
+        let start = Instant::now();
+
+        loop {
+            if let Some(path) = self.find_sysfs()? {
+                return Ok(path);
+            }
+
+            if start.elapsed() > timeout {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "hidraw device isn't created",
+                ));
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn find_sysfs(&self) -> io::Result<Option<PathBuf>> {
+        let needle = format!("HID_UNIQ={}\n", self.uniq);
+
+        for entry in fs::read_dir("/sys/bus/hid/devices")? {
+            let path = entry?.path();
+            let Ok(uevent) = fs::read_to_string(path.join("uevent")) else {
+                continue;
+            };
+
+            // device is usable after driver creates hidraw node
+            if uevent.contains(&needle) && has_hidraw(&path) {
+                return Ok(Some(path));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reports which are received since last call
+    pub fn take_reports(&self) -> Vec<Report> {
+        std::mem::take(&mut self.reports.lock().unwrap())
+    }
+}
+
+impl Drop for VirtualKeyboard {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+
+        if let Err(err) = Event::new(UHID_DESTROY).send(&mut &*self.uhid) {
+            log::error!("uhid: failed to destroy device: {err}");
+        }
+    }
+}
+
+fn has_hidraw(path: &Path) -> bool {
+    fs::read_dir(path.join("hidraw")).is_ok_and(|mut dir| dir.next().is_some())
+}
+
+/// Answers requests of kernel until `stop` is set
+fn serve(uhid: &File, reports: &Mutex<Vec<Report>>, stop: &AtomicBool) -> io::Result<()> {
+    let mut fd = libc::pollfd {
+        fd: uhid.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+
+    while !stop.load(Ordering::Relaxed) {
+        // SAFETY: `fd` is valid `pollfd`
+        let ret = unsafe { libc::poll(&mut fd, 1, 50) };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        if ret == 0 {
+            continue;
+        }
+
+        let event = Event::receive(uhid)?;
+        match event.r#type() {
+            UHID_SET_REPORT => {
+                // struct uhid_set_report_req { id, rnum, rtype, size, data }
+                let id = event.u32(0);
+                let size = usize::from(event.u16(6)).min(UHID_DATA_MAX);
+                reports.lock().unwrap().push(Report {
+                    r#type: ReportType::from_raw(event.u8(5)),
+                    data: event.get(8, size).to_vec(),
+                });
+
+                let mut reply = Event::new(UHID_SET_REPORT_REPLY);
+                reply.put(0, &id.to_ne_bytes());
+                reply.send(&mut &*uhid)?;
+            }
+            UHID_GET_REPORT => {
+                // reading isn't emulated, reply with error
+                let mut reply = Event::new(UHID_GET_REPORT_REPLY);
+                reply.put(0, &event.u32(0).to_ne_bytes());
+                reply.put(4, &(libc::EIO as u16).to_ne_bytes());
+                reply.send(&mut &*uhid)?;
+            }
+            UHID_OUTPUT => {
+                // struct uhid_output_req { data, size, rtype }
+                let size = usize::from(event.u16(UHID_DATA_MAX)).min(UHID_DATA_MAX);
+                reports.lock().unwrap().push(Report {
+                    r#type: ReportType::from_raw(event.u8(UHID_DATA_MAX + 2)),
+                    data: event.get(0, size).to_vec(),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Packed `struct uhid_event`, offsets are counted from start of union `u`
+struct Event(Box<[u8; UHID_EVENT_SIZE]>);
+
+impl Event {
+    fn new(r#type: u32) -> Self {
+        let mut event = Self(Box::new([0u8; UHID_EVENT_SIZE]));
+        event.0[..4].copy_from_slice(&r#type.to_ne_bytes());
+        event
+    }
+
+    fn receive(mut uhid: &File) -> io::Result<Self> {
+        let mut event = Self::new(0);
+        if uhid.read(event.0.as_mut_slice())? < 4 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        Ok(event)
+    }
+
+    fn send(&self, uhid: &mut impl io::Write) -> io::Result<()> {
+        uhid.write_all(self.0.as_slice())
+    }
+
+    fn r#type(&self) -> u32 {
+        u32::from_ne_bytes(self.0[..4].try_into().unwrap())
+    }
+
+    fn get(&self, offset: usize, len: usize) -> &[u8] {
+        &self.0[4 + offset..][..len]
+    }
+
+    fn u8(&self, offset: usize) -> u8 {
+        self.get(offset, 1)[0]
+    }
+
+    fn u16(&self, offset: usize) -> u16 {
+        u16::from_ne_bytes(self.get(offset, 2).try_into().unwrap())
+    }
+
+    fn u32(&self, offset: usize) -> u32 {
+        u32::from_ne_bytes(self.get(offset, 4).try_into().unwrap())
+    }
+
+    fn put(&mut self, offset: usize, data: &[u8]) {
+        self.0[4 + offset..][..data.len()].copy_from_slice(data);
+    }
+
+    fn put_str(&mut self, offset: usize, max: usize, value: &str) {
+        // keep terminating zero
+        let value = &value.as_bytes()[..value.len().min(max - 1)];
+        self.put(offset, value);
+    }
+}
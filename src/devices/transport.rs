@@ -0,0 +1,130 @@
+use crate::Error;
+use serde::Deserialize;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+pub mod bpf;
+pub mod capture;
+pub mod dump;
+pub mod hidraw;
+pub mod recording;
+
+pub use bpf::BpfTransport;
+pub use capture::CaptureTransport;
+pub use dump::DumpTransport;
+pub use hidraw::HidrawTransport;
+pub use recording::Packet;
+pub use recording::RecordingTransport;
+
+/// How report is delivered to device
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReportType {
+    /// SET_REPORT request with feature report
+    #[default]
+    Feature,
+    /// SET_REPORT request with output report, sent through control endpoint
+    Output,
+    /// Output report sent through interrupt OUT endpoint, like `write()` to hidraw
+    OutputInterrupt,
+}
+
+impl fmt::Display for ReportType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReportType::Feature => write!(f, "feature"),
+            ReportType::Output => write!(f, "output"),
+            ReportType::OutputInterrupt => write!(f, "output-interrupt"),
+        }
+    }
+}
+
+/// Backend which delivers feature reports to HID device
+pub trait Transport: Send + Sync {
+    fn write(&mut self, hid: u16, data: &[u8]) -> Result<(), Error>;
+
+    /// Sends report of any type, by default only feature reports are supported
+    fn write_report(
+        &mut self,
+        hid: u16,
+        report_type: ReportType,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        match report_type {
+            ReportType::Feature => self.write(hid, data),
+            _ => Err(unsupported(hid, &format!("{report_type} reports"))),
+        }
+    }
+
+    /// Sends reports in order, transports which can deliver them at once override it
+    fn write_batch(
+        &mut self,
+        hid: u16,
+        report_type: ReportType,
+        packets: &[&[u8]],
+    ) -> Result<(), Error> {
+        for data in packets {
+            self.write_report(hid, report_type, data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Requests feature report with GET_REPORT, `data[0]` is report ID and the rest is
+    /// filled with reply. Returns size of reply including report ID.
+    fn read(&mut self, hid: u16, data: &mut [u8]) -> Result<usize, Error> {
+        let _ = data;
+
+        Err(unsupported(hid, "reading reports"))
+    }
+}
+
+fn unsupported(hid: u16, what: &str) -> Error {
+    Error::Transport {
+        hid,
+        source: io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{what} isn't supported by transport"),
+        ),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TransportKind {
+    /// Use HID-BPF if kernel supports it, otherwise fallback to hidraw
+    #[default]
+    Auto,
+    /// Use HID-BPF syscall program
+    Bpf,
+    /// Use `/dev/hidrawN` devices
+    Hidraw,
+    /// Print reports to stdout instead of sending them
+    DryRun,
+}
+
+impl TransportKind {
+    /// Creates backend, `sysfs_root` is used for looking up hidraw devices
+    pub fn open(self, sysfs_root: &Path) -> Result<Box<dyn Transport>, Error> {
+        match self {
+            TransportKind::Auto => match BpfTransport::new() {
+                Ok(transport) => Ok(Box::new(transport)),
+                Err(err) => {
+                    log::info!("HID-BPF is unavailable ({err}), fallback to hidraw");
+                    Ok(Box::new(HidrawTransport::new(sysfs_root)))
+                }
+            },
+            TransportKind::Bpf => Ok(Box::new(BpfTransport::new()?)),
+            TransportKind::Hidraw => Ok(Box::new(HidrawTransport::new(sysfs_root))),
+            TransportKind::DryRun => Ok(Box::new(DumpTransport::stdout())),
+        }
+    }
+}
+
+fn check_size(size: usize, max: usize) -> Result<(), Error> {
+    if size > max {
+        return Err(Error::ReportTooBig { size, max });
+    }
+
+    Ok(())
+}
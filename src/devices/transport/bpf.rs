@@ -0,0 +1,392 @@
+use super::check_size;
+use super::ReportType;
+use super::Transport;
+use crate::devices::write_bpf;
+use crate::utils::AsBytes as _;
+use crate::Error;
+use libbpf_rs::libbpf_sys;
+use libbpf_rs::skel::OpenSkel as _;
+use libbpf_rs::skel::SkelBuilder as _;
+use libbpf_rs::MapCore as _;
+use libbpf_rs::MapFlags;
+use libbpf_rs::MapHandle;
+use std::ffi::CString;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::mem::size_of;
+use std::mem::size_of_val;
+use std::mem::MaybeUninit;
+use std::os::fd::AsFd as _;
+use std::os::fd::AsRawFd as _;
+use std::os::fd::FromRawFd as _;
+use std::os::fd::OwnedFd;
+use std::os::unix::ffi::OsStrExt as _;
+use std::path::Path;
+use std::path::PathBuf;
+use std::ptr::copy_nonoverlapping;
+use write_bpf::types::Batch;
+use write_bpf::types::Hdr;
+use write_bpf::WriteSkel;
+use write_bpf::WriteSkelBuilder;
+
+/// Directory in bpffs where `a4keyboard pin` keeps the program and its map
+pub const DEFAULT_PIN_DIR: &str = "/sys/fs/bpf/a4keyboard";
+
+/// Names of pinned objects end with version of `write.bpf.c` interface, which is bumped
+/// when `Hdr` or `Batch` changes, so objects pinned by older a4keyboard aren't used
+pub const PINNED_PROGRAM: &str = "write.v2";
+pub const PINNED_MAP: &str = "array.v2";
+/// Prefixes of pinned objects of every version
+const PINNED_NAMES: [&str; 2] = ["write", "array"];
+
+// `enum hid_class_request` from `linux/hid.h`
+const HID_REQ_GET_REPORT: u32 = 0x01;
+const HID_REQ_SET_REPORT: u32 = 0x09;
+/// Sends report with `hid_bpf_hw_output_report`, the same as in `write.bpf.c`
+const REQUEST_OUTPUT_REPORT: u32 = 0x00;
+/// Reports which are sent by one run of program, the same as in `write.bpf.c`
+const MAX_BATCH: usize = 8;
+
+// `enum hid_report_type` from `linux/hid.h`
+const HID_OUTPUT_REPORT: u32 = 1;
+const HID_FEATURE_REPORT: u32 = 2;
+
+/// Sends reports through `write.bpf.c` syscall program
+///
+/// Loading the program requires `CAP_BPF`, program which is pinned in bpffs can be used
+/// by anyone who has access to pinned files.
+pub struct BpfTransport {
+    program: OwnedFd,
+    map: MapHandle,
+    /// Pin directory which is locked while pinned map is used, as it's shared with other
+    /// processes
+    pin_dir: Option<File>,
+}
+
+impl BpfTransport {
+    /// Uses program pinned in [`DEFAULT_PIN_DIR`] if it exists and is up to date, otherwise
+    /// loads it
+    pub fn new() -> Result<Self, Error> {
+        let dir = Path::new(DEFAULT_PIN_DIR);
+        if dir.join(PINNED_PROGRAM).exists() {
+            match Self::pinned(dir) {
+                Ok(transport) => return Ok(transport),
+                Err(err) => log::warn!("{err}, loading HID-BPF program instead"),
+            }
+        } else if !pins(dir).unwrap_or_default().is_empty() {
+            log::warn!(
+                "HID-BPF program pinned in {} is outdated, run `a4keyboard pin` again",
+                dir.display()
+            );
+        }
+
+        Self::load()
+    }
+
+    /// Loads program which is unloaded when transport is dropped
+    pub fn load() -> Result<Self, Error> {
+        Self::load_with(|_| Ok(()))
+    }
+
+    fn load_with(f: impl FnOnce(&mut WriteSkel) -> Result<(), Error>) -> Result<Self, Error> {
+        let mut object = MaybeUninit::uninit();
+        let mut program = WriteSkelBuilder::default().open(&mut object)?.load()?;
+        f(&mut program)?;
+
+        // duplicated descriptors keep program and map loaded after skeleton is dropped
+        Ok(Self {
+            program: program.progs.write.as_fd().try_clone_to_owned()?,
+            map: MapHandle::try_from(&program.maps.array)?,
+            pin_dir: None,
+        })
+    }
+
+    /// Uses program which is pinned in `dir` by [`BpfTransport::pin`]
+    pub fn pinned(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let dir = dir.as_ref();
+
+        let map = MapHandle::from_pinned_path(dir.join(PINNED_MAP))?;
+
+        let path = CString::new(dir.join(PINNED_PROGRAM).as_os_str().as_bytes())
+            .map_err(io::Error::from)?;
+        // SAFETY: `path` is valid C string
+        let fd = unsafe { libbpf_sys::bpf_obj_get(path.as_ptr()) };
+        if fd < 0 {
+            return Err(libbpf_rs::Error::from_raw_os_error(-fd).into());
+        }
+        // SAFETY: descriptor is just returned by `bpf_obj_get` and isn't owned by anything else
+        let program = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // pinned by another version of a4keyboard
+        if map.value_size() as usize != size_of::<Batch>() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "HID-BPF program pinned in {} is outdated, run `a4keyboard pin` again",
+                    dir.display()
+                ),
+            )
+            .into());
+        }
+
+        Ok(Self {
+            program,
+            map,
+            pin_dir: Some(File::open(dir).map_err(Error::file(dir))?),
+        })
+    }
+
+    /// Loads program and pins it with its map in `dir`, which is created if needed.
+    /// Previously pinned objects are replaced.
+    pub fn pin(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let dir = dir.as_ref();
+
+        remove_pins(dir).map_err(Error::file(dir))?;
+        fs::create_dir_all(dir).map_err(Error::file(dir))?;
+
+        let mut transport = Self::load_with(|program| {
+            program.maps.array.pin(dir.join(PINNED_MAP))?;
+            program.progs.write.pin(dir.join(PINNED_PROGRAM))?;
+            Ok(())
+        })?;
+        transport.pin_dir = Some(File::open(dir).map_err(Error::file(dir))?);
+
+        Ok(transport)
+    }
+
+    /// Removes objects pinned by [`BpfTransport::pin`] and `dir` itself, program is unloaded
+    /// when the last transport which uses it is dropped
+    pub fn unpin(dir: impl AsRef<Path>) -> Result<(), Error> {
+        let dir = dir.as_ref();
+
+        remove_pins(dir).map_err(Error::file(dir))?;
+        match fs::remove_dir(dir) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(Error::file(dir)(err)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Locks pin directory until returned guard is dropped, does nothing if map isn't pinned
+    fn lock(&self) -> io::Result<Option<PinLock<'_>>> {
+        self.pin_dir.as_ref().map(PinLock::new).transpose()
+    }
+
+    /// Runs program once with `packets` in the map, returns size of the last transferred
+    /// report. Packets must have the same size and fit into one batch.
+    ///
+    /// Map is shared if program is pinned, so caller holds [`BpfTransport::lock`] until
+    /// results are read from map.
+    fn request(
+        &self,
+        hid: u16,
+        packets: &[&[u8]],
+        request: u32,
+        report_type: u32,
+    ) -> Result<usize, Error> {
+        debug_assert!(!packets.is_empty() && packets.len() <= MAX_BATCH);
+
+        let mut batch = Batch::default();
+        for (block, data) in batch.blocks.iter_mut().zip(packets) {
+            check_size(data.len(), size_of_val(block))?;
+
+            // SAFETY: sizes is checked above
+            unsafe {
+                copy_nonoverlapping(data.as_ptr(), block.as_bytes_mut().as_mut_ptr(), data.len())
+            };
+        }
+
+        self.map
+            .update(&[0u8; 4], batch.as_bytes(), MapFlags::empty())?;
+
+        let mut hdr = Hdr {
+            hid_id: hid as u32,
+            data_size: packets[0].len() as u32,
+            request,
+            report_type,
+            count: packets.len() as u32,
+        };
+
+        // SAFETY: all-zero value is valid for C struct of integers and pointers
+        let mut opts: libbpf_sys::bpf_test_run_opts = unsafe { std::mem::zeroed() };
+        opts.sz = size_of_val(&opts) as _;
+        opts.ctx_in = hdr.as_bytes_mut().as_ptr().cast();
+        opts.ctx_size_in = size_of_val(&hdr) as _;
+
+        // SAFETY: `opts` points to `hdr` which outlives the call
+        let ret =
+            unsafe { libbpf_sys::bpf_prog_test_run_opts(self.program.as_raw_fd(), &mut opts) };
+        if ret < 0 {
+            return Err(libbpf_rs::Error::from_raw_os_error(-ret).into());
+        }
+
+        // program returns negative errno on failure
+        let ret = opts.retval as i32;
+        if ret < 0 {
+            return Err(Error::Transport {
+                hid,
+                source: io::Error::from_raw_os_error(-ret),
+            });
+        }
+
+        Ok(ret as usize)
+    }
+}
+
+/// Exclusive `flock` of pin directory, which is released when dropped
+struct PinLock<'a>(&'a File);
+
+impl<'a> PinLock<'a> {
+    fn new(dir: &'a File) -> io::Result<Self> {
+        loop {
+            // SAFETY: descriptor is owned by `dir`
+            if unsafe { libc::flock(dir.as_raw_fd(), libc::LOCK_EX) } == 0 {
+                return Ok(Self(dir));
+            }
+
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+    }
+}
+
+impl Drop for PinLock<'_> {
+    fn drop(&mut self) {
+        // SAFETY: descriptor is owned by locked file; lock is released with descriptor anyway
+        unsafe { libc::flock(self.0.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
+
+/// Returns objects in `dir` which are pinned by any version of a4keyboard
+fn pins(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut pins = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let pinned = PINNED_NAMES.iter().any(|prefix| {
+            name.strip_prefix(prefix)
+                .is_some_and(|version| version.is_empty() || version.starts_with(".v"))
+        });
+        if pinned {
+            pins.push(entry.path());
+        }
+    }
+
+    Ok(pins)
+}
+
+fn remove_pins(dir: &Path) -> io::Result<()> {
+    let pins = match pins(dir) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        pins => pins?,
+    };
+    for path in pins {
+        match fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+impl Transport for BpfTransport {
+    fn write(&mut self, hid: u16, data: &[u8]) -> Result<(), Error> {
+        self.write_report(hid, ReportType::Feature, data)
+    }
+
+    fn write_report(
+        &mut self,
+        hid: u16,
+        report_type: ReportType,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        self.write_batch(hid, report_type, &[data])
+    }
+
+    fn write_batch(
+        &mut self,
+        hid: u16,
+        report_type: ReportType,
+        packets: &[&[u8]],
+    ) -> Result<(), Error> {
+        let (request, report_type) = match report_type {
+            ReportType::Feature => (HID_REQ_SET_REPORT, HID_FEATURE_REPORT),
+            ReportType::Output => (HID_REQ_SET_REPORT, HID_OUTPUT_REPORT),
+            ReportType::OutputInterrupt => (REQUEST_OUTPUT_REPORT, HID_OUTPUT_REPORT),
+        };
+
+        // program takes blocks of the same size
+        for same_size in packets.chunk_by(|a, b| a.len() == b.len()) {
+            for batch in same_size.chunks(MAX_BATCH) {
+                let _lock = self.lock()?;
+                self.request(hid, batch, request, report_type)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read(&mut self, hid: u16, data: &mut [u8]) -> Result<usize, Error> {
+        // reply stays in map until it's read
+        let _lock = self.lock()?;
+        let size = self.request(hid, &[data], HID_REQ_GET_REPORT, HID_FEATURE_REPORT)?;
+
+        let reply = self
+            .map
+            .lookup(&[0u8; 4], MapFlags::ANY)?
+            .unwrap_or_default();
+        let size = size.min(data.len()).min(reply.len());
+        data[..size].copy_from_slice(&reply[..size]);
+
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::BpfTransport;
+    use super::PinLock;
+    use super::PINNED_MAP;
+    use super::PINNED_PROGRAM;
+    use std::fs;
+    use std::fs::File;
+    use std::os::fd::AsRawFd as _;
+
+    #[test]
+    fn unpin() {
+        let dir = std::env::temp_dir().join(format!("a4keyboard-unpin-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(PINNED_PROGRAM), "").unwrap();
+        fs::write(dir.join(PINNED_MAP), "").unwrap();
+        // pinned by older version
+        fs::write(dir.join("write"), "").unwrap();
+        fs::write(dir.join("array.v1"), "").unwrap();
+
+        BpfTransport::unpin(&dir).unwrap();
+        assert!(!dir.exists());
+
+        // nothing is pinned
+        BpfTransport::unpin(&dir).unwrap();
+    }
+
+    #[test]
+    fn pin_lock() {
+        let dir = std::env::temp_dir().join(format!("a4keyboard-lock-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let locked = File::open(&dir).unwrap();
+        let other = File::open(&dir).unwrap();
+        // SAFETY: descriptor is owned by `other`
+        let try_lock = || unsafe { libc::flock(other.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+
+        let lock = PinLock::new(&locked).unwrap();
+        assert_eq!(try_lock(), -1);
+        drop(lock);
+        assert_eq!(try_lock(), 0);
+
+        fs::remove_dir(&dir).unwrap();
+    }
+}
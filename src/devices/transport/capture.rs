@@ -0,0 +1,294 @@
+//! Text file with reports and time when they were sent
+//!
+//! ```text
+//! # a4keyboard capture v1
+//! # TIME_US HID DATA
+//! 0 0005 07030601000000000000...
+//! 1520 0005 07030600000000000100...
+//! 2040 0007 0100ff00 output
+//! ```
+//!
+//! Type of report follows data if it isn't feature report.
+
+use super::ReportType;
+use super::Transport;
+use crate::Error;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+const HEADER: &str = "# a4keyboard capture v1";
+
+#[derive(Debug, thiserror::Error)]
+pub enum CaptureError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("not a capture file")]
+    Header,
+    #[error("line {0}: expected `TIME_US HID DATA [TYPE]`")]
+    Line(usize),
+}
+
+/// Report sent at `time` since start of capture, reports of one batch have the same time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub time: Duration,
+    pub hid: u16,
+    pub report_type: ReportType,
+    pub data: Vec<u8>,
+}
+
+impl Record {
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let time = fields.next()?.parse().ok()?;
+        let hid = u16::from_str_radix(fields.next()?, 16).ok()?;
+        let data = fields.next()?;
+        let report_type = match fields.next() {
+            None => ReportType::Feature,
+            Some("output") => ReportType::Output,
+            Some("output-interrupt") => ReportType::OutputInterrupt,
+            Some(_) => return None,
+        };
+        if fields.next().is_some() || data.len() % 2 != 0 {
+            return None;
+        }
+
+        let data = (0..data.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(data.get(i..i + 2)?, 16).ok())
+            .collect::<Option<_>>()?;
+
+        Some(Self {
+            time: Duration::from_micros(time),
+            hid,
+            report_type,
+            data,
+        })
+    }
+}
+
+/// Writes records in capture format
+pub struct CaptureWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        writeln!(out, "{HEADER}")?;
+        writeln!(out, "# TIME_US HID DATA")?;
+
+        Ok(Self { out })
+    }
+
+    pub fn write(&mut self, record: &Record) -> io::Result<()> {
+        write!(self.out, "{} {:04x} ", record.time.as_micros(), record.hid)?;
+        for byte in &record.data {
+            write!(self.out, "{byte:02x}")?;
+        }
+        if record.report_type != ReportType::Feature {
+            write!(self.out, " {}", record.report_type)?;
+        }
+        writeln!(self.out)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// Reads all records, comments and empty lines are skipped
+pub fn read_capture(input: impl BufRead) -> Result<Vec<Record>, CaptureError> {
+    let mut lines = input.lines();
+    if lines.next().transpose()?.as_deref() != Some(HEADER) {
+        return Err(CaptureError::Header);
+    }
+
+    let mut records = Vec::new();
+    for (idx, line) in lines.enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // header is the first line
+        records.push(Record::parse(line).ok_or(CaptureError::Line(idx + 2))?);
+    }
+
+    Ok(records)
+}
+
+pub fn load_capture(path: impl AsRef<Path>) -> Result<Vec<Record>, Error> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(CaptureError::from);
+
+    file.and_then(|file| read_capture(BufReader::new(file)))
+        .map_err(|source| Error::Capture {
+            path: path.to_owned(),
+            source,
+        })
+}
+
+/// Records every report to capture file and passes it to another transport
+pub struct CaptureTransport {
+    inner: Box<dyn Transport>,
+    writer: CaptureWriter<BufWriter<File>>,
+    path: PathBuf,
+    start: Instant,
+}
+
+impl CaptureTransport {
+    pub fn create(inner: Box<dyn Transport>, path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let writer = File::create(path)
+            .and_then(|file| CaptureWriter::new(BufWriter::new(file)))
+            .map_err(Error::file(path))?;
+
+        Ok(Self {
+            inner,
+            writer,
+            path: path.to_owned(),
+            start: Instant::now(),
+        })
+    }
+}
+
+impl Transport for CaptureTransport {
+    fn write(&mut self, hid: u16, data: &[u8]) -> Result<(), Error> {
+        self.write_report(hid, ReportType::Feature, data)
+    }
+
+    fn write_report(
+        &mut self,
+        hid: u16,
+        report_type: ReportType,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        self.writer
+            .write(&Record {
+                time: self.start.elapsed(),
+                hid,
+                report_type,
+                data: data.to_vec(),
+            })
+            .map_err(Error::file(&self.path))?;
+
+        self.inner.write_report(hid, report_type, data)
+    }
+
+    fn write_batch(
+        &mut self,
+        hid: u16,
+        report_type: ReportType,
+        packets: &[&[u8]],
+    ) -> Result<(), Error> {
+        // batch is sent at once, so its packets share time and are replayed as burst
+        let time = self.start.elapsed();
+        for data in packets {
+            self.writer
+                .write(&Record {
+                    time,
+                    hid,
+                    report_type,
+                    data: data.to_vec(),
+                })
+                .map_err(Error::file(&self.path))?;
+        }
+
+        self.inner.write_batch(hid, report_type, packets)
+    }
+
+    // replies aren't recorded, replay only sends reports
+    fn read(&mut self, hid: u16, data: &mut [u8]) -> Result<usize, Error> {
+        self.inner.read(hid, data)
+    }
+}
+
+impl Drop for CaptureTransport {
+    fn drop(&mut self) {
+        if let Err(err) = self.writer.flush() {
+            log::error!("{}: failed to write capture: {err}", self.path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::read_capture;
+    use super::CaptureError;
+    use super::CaptureWriter;
+    use super::Record;
+    use crate::devices::transport::ReportType;
+    use std::time::Duration;
+
+    #[test]
+    fn write_and_read() {
+        let records = [
+            Record {
+                time: Duration::ZERO,
+                hid: 5,
+                report_type: ReportType::Feature,
+                data: vec![0x07, 0x03, 0x06, 0x01],
+            },
+            Record {
+                time: Duration::from_micros(1520),
+                hid: 0x1a,
+                report_type: ReportType::Output,
+                data: vec![0xff],
+            },
+        ];
+
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        for record in &records {
+            writer.write(record).unwrap();
+        }
+        let capture = String::from_utf8(writer.into_inner()).unwrap();
+
+        assert_eq!(
+            capture,
+            "# a4keyboard capture v1\n\
+             # TIME_US HID DATA\n\
+             0 0005 07030601\n\
+             1520 001a ff output\n"
+        );
+        assert_eq!(read_capture(capture.as_bytes()).unwrap(), records);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(
+            read_capture("0 0005 00\n".as_bytes()),
+            Err(CaptureError::Header)
+        ));
+
+        let capture = "# a4keyboard capture v1\n\n0 0005 0\n";
+        assert!(matches!(
+            read_capture(capture.as_bytes()),
+            Err(CaptureError::Line(3))
+        ));
+
+        let capture = "# a4keyboard capture v1\n0 0005 00 input\n";
+        assert!(matches!(
+            read_capture(capture.as_bytes()),
+            Err(CaptureError::Line(2))
+        ));
+
+        let capture = "# a4keyboard capture v1\n0 xyz 00\n";
+        assert!(matches!(
+            read_capture(capture.as_bytes()),
+            Err(CaptureError::Line(2))
+        ));
+    }
+}
@@ -0,0 +1,107 @@
+use super::ReportType;
+use super::Transport;
+use crate::devices::bloody::protocol::Command;
+use crate::Error;
+use std::io;
+use std::io::Write;
+
+const BYTES_PER_LINE: usize = 16;
+
+/// Prints reports as hex dump instead of sending them
+pub struct DumpTransport<W> {
+    out: W,
+}
+
+impl DumpTransport<io::Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl<W: Write> DumpTransport<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn dump(&mut self, hid: u16, report_type: ReportType, data: &[u8]) -> io::Result<()> {
+        let out = &mut self.out;
+
+        write!(out, "HID {hid:04X}: {} bytes", data.len())?;
+        match (report_type, Command::decode(data)) {
+            (ReportType::Feature, Ok(command)) => writeln!(out, ", bloody {command}")?,
+            (ReportType::Feature, Err(_)) => writeln!(out)?,
+            (report_type, _) => writeln!(out, ", {report_type} report")?,
+        }
+
+        hex_dump(out, data)
+    }
+}
+
+/// Writes `data` by 16 bytes per line, indented by two spaces
+pub fn hex_dump(out: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    for line in data.chunks(BYTES_PER_LINE) {
+        write!(out, " ")?;
+        for byte in line {
+            write!(out, " {byte:02x}")?;
+        }
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+impl<W: Write + Send + Sync> Transport for DumpTransport<W> {
+    fn write(&mut self, hid: u16, data: &[u8]) -> Result<(), Error> {
+        self.write_report(hid, ReportType::Feature, data)
+    }
+
+    fn write_report(
+        &mut self,
+        hid: u16,
+        report_type: ReportType,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        Ok(self.dump(hid, report_type, data)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DumpTransport;
+    use crate::devices::bloody::protocol::Command;
+    use crate::devices::transport::ReportType;
+    use crate::devices::transport::Transport as _;
+
+    #[test]
+    fn dump() {
+        let mut transport = DumpTransport::new(Vec::new());
+        transport
+            .write(5, &Command::SetControl { software: true }.encode())
+            .unwrap();
+        transport.write(9, &[0x01, 0x02, 0x03]).unwrap();
+        transport
+            .write_report(9, ReportType::OutputInterrupt, &[0x04])
+            .unwrap();
+
+        let out = String::from_utf8(transport.into_inner()).unwrap();
+        let zeros = " 00".repeat(16);
+        assert_eq!(
+            out,
+            format!(
+                "HID 0005: 64 bytes, bloody set control: software\n\
+                 \x20 07 03 06 00 00 00 00 00 01 00 00 00 00 00 00 00\n\
+                 \x20{zeros}\n\
+                 \x20{zeros}\n\
+                 \x20{zeros}\n\
+                 HID 0009: 3 bytes\n\
+                 \x20 01 02 03\n\
+                 HID 0009: 1 bytes, output-interrupt report\n\
+                 \x20 04\n"
+            )
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use super::check_size;
+use super::ReportType;
+use super::Transport;
+use crate::devices::from_hex;
+use crate::devices::hid_devices_dir;
+use crate::Error;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write as _;
+use std::os::fd::AsRawFd as _;
+use std::path::Path;
+use std::path::PathBuf;
+
+const MAX_REPORT_SIZE: usize = 4096;
+
+const IOC_WRITE_READ: u64 = 3;
+
+/// `HIDIOCSFEATURE(len)` from `linux/hidraw.h`
+const fn hidiocsfeature(len: usize) -> u64 {
+    (IOC_WRITE_READ << 30) | ((len as u64) << 16) | ((b'H' as u64) << 8) | 0x06
+}
+
+/// `HIDIOCSOUTPUT(len)` from `linux/hidraw.h`
+const fn hidiocsoutput(len: usize) -> u64 {
+    (IOC_WRITE_READ << 30) | ((len as u64) << 16) | ((b'H' as u64) << 8) | 0x0b
+}
+
+/// `HIDIOCGFEATURE(len)` from `linux/hidraw.h`
+const fn hidiocgfeature(len: usize) -> u64 {
+    (IOC_WRITE_READ << 30) | ((len as u64) << 16) | ((b'H' as u64) << 8) | 0x07
+}
+
+/// Sends feature reports through `/dev/hidrawN` devices
+pub struct HidrawTransport {
+    sysfs_root: PathBuf,
+    files: HashMap<u16, File>,
+}
+
+impl HidrawTransport {
+    pub fn new(sysfs_root: impl Into<PathBuf>) -> Self {
+        Self {
+            sysfs_root: sysfs_root.into(),
+            files: HashMap::new(),
+        }
+    }
+
+    fn find_hidraw(sysfs_root: &Path, hid: u16) -> io::Result<PathBuf> {
+        for device_dir in fs::read_dir(hid_devices_dir(sysfs_root))? {
+            let device_dir = device_dir?;
+            let name = device_dir.file_name();
+            let name = name.as_encoded_bytes();
+
+            if name.len() != 19 || from_hex(&name[15..19]) != Some(hid) {
+                continue;
+            }
+
+            if let Some(hidraw) = fs::read_dir(device_dir.path().join("hidraw"))?.next() {
+                return Ok(PathBuf::from("/dev").join(hidraw?.file_name()));
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("hidraw device for HID {hid:04X} is not found"),
+        ))
+    }
+
+    fn file(&mut self, hid: u16) -> Result<&File, Error> {
+        let file = match self.files.entry(hid) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let path = Self::find_hidraw(&self.sysfs_root, hid)
+                    .map_err(|source| Error::Transport { hid, source })?;
+                let file = match File::options().read(true).write(true).open(&path) {
+                    Ok(file) => file,
+                    Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+                        return Err(Error::PermissionDenied {
+                            what: path.display().to_string(),
+                            source: err.into(),
+                        });
+                    }
+                    Err(source) => return Err(Error::Transport { hid, source }),
+                };
+                entry.insert(file)
+            }
+        };
+
+        Ok(file)
+    }
+}
+
+impl Transport for HidrawTransport {
+    fn write(&mut self, hid: u16, data: &[u8]) -> Result<(), Error> {
+        self.write_report(hid, ReportType::Feature, data)
+    }
+
+    fn write_report(
+        &mut self,
+        hid: u16,
+        report_type: ReportType,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        check_size(data.len(), MAX_REPORT_SIZE)?;
+
+        let mut file = self.file(hid)?;
+        let request = match report_type {
+            ReportType::Feature => hidiocsfeature(data.len()),
+            ReportType::Output => hidiocsoutput(data.len()),
+            ReportType::OutputInterrupt => {
+                // hidraw sends written reports to interrupt OUT endpoint if device has it
+                return file
+                    .write_all(data)
+                    .map_err(|source| Error::Transport { hid, source });
+            }
+        };
+
+        // SAFETY: `HIDIOCSFEATURE` and `HIDIOCSOUTPUT` only read `data.len()` bytes from `data`
+        let ret = unsafe { libc::ioctl(file.as_raw_fd(), request as _, data.as_ptr()) };
+        if ret < 0 {
+            return Err(Error::Transport {
+                hid,
+                source: io::Error::last_os_error(),
+            });
+        }
+
+        Ok(())
+    }
+
+    fn read(&mut self, hid: u16, data: &mut [u8]) -> Result<usize, Error> {
+        check_size(data.len(), MAX_REPORT_SIZE)?;
+
+        let fd = self.file(hid)?.as_raw_fd();
+
+        // SAFETY: `HIDIOCGFEATURE` writes at most `data.len()` bytes to `data`
+        let ret = unsafe { libc::ioctl(fd, hidiocgfeature(data.len()) as _, data.as_mut_ptr()) };
+        if ret < 0 {
+            return Err(Error::Transport {
+                hid,
+                source: io::Error::last_os_error(),
+            });
+        }
+
+        Ok(ret as usize)
+    }
+}
@@ -0,0 +1,84 @@
+use super::ReportType;
+use super::Transport;
+use crate::Error;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub hid: u16,
+    pub report_type: ReportType,
+    pub data: Vec<u8>,
+}
+
+/// Keeps every sent report in memory instead of sending it to device
+///
+/// Clones share the same list of packets, so one clone can be given to
+/// [`Devices`](crate::devices::Devices) and another one can be used for inspection.
+#[derive(Debug, Clone, Default)]
+pub struct RecordingTransport {
+    packets: Arc<Mutex<Vec<Packet>>>,
+    replies: Arc<Mutex<HashMap<u8, Vec<u8>>>>,
+}
+
+impl RecordingTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns all recorded packets
+    pub fn packets(&self) -> Vec<Packet> {
+        self.packets.lock().unwrap().clone()
+    }
+
+    /// Returns all recorded packets and clears the record
+    pub fn take_packets(&self) -> Vec<Packet> {
+        std::mem::take(&mut *self.packets.lock().unwrap())
+    }
+
+    /// Sets reply to reading of report which ID is `report[0]`, for any HID ID
+    pub fn set_report(&self, report: &[u8]) {
+        self.replies
+            .lock()
+            .unwrap()
+            .insert(report[0], report.to_vec());
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn write(&mut self, hid: u16, data: &[u8]) -> Result<(), Error> {
+        self.write_report(hid, ReportType::Feature, data)
+    }
+
+    fn write_report(
+        &mut self,
+        hid: u16,
+        report_type: ReportType,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        self.packets.lock().unwrap().push(Packet {
+            hid,
+            report_type,
+            data: data.to_vec(),
+        });
+
+        Ok(())
+    }
+
+    fn read(&mut self, hid: u16, data: &mut [u8]) -> Result<usize, Error> {
+        let replies = self.replies.lock().unwrap();
+        let Some(report) = data.first().and_then(|id| replies.get(id)) else {
+            return Err(Error::Transport {
+                hid,
+                source: io::Error::new(io::ErrorKind::NotFound, "report isn't set"),
+            });
+        };
+
+        let size = report.len().min(data.len());
+        data[..size].copy_from_slice(&report[..size]);
+
+        Ok(size)
+    }
+}
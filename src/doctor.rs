@@ -0,0 +1,391 @@
+//! Checks of kernel features and permissions which are needed for controlling keyboards
+
+use crate::devices::definition;
+use crate::devices::transport::bpf::DEFAULT_PIN_DIR;
+use crate::devices::transport::bpf::PINNED_MAP;
+use crate::devices::transport::bpf::PINNED_PROGRAM;
+use crate::devices::transport::BpfTransport;
+use crate::devices::Devices;
+use crate::devices::DEFAULT_SYSFS_ROOT;
+use crate::Error;
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::os::unix::ffi::OsStrExt as _;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// The first kernel with HID-BPF syscall programs and `hid_bpf_hw_request`
+const MIN_KERNEL: (u32, u32) = (6, 3);
+
+const CAP_SYS_ADMIN: u32 = 21;
+const CAP_BPF: u32 = 39;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    /// Optional feature is missing
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Status::Pass => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        f.pad(name)
+    }
+}
+
+/// Result of one check with suggested fix if it isn't passed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub message: String,
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Pass,
+            message: message.into(),
+            fix: None,
+        }
+    }
+
+    fn fail(name: &'static str, message: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Fail,
+            message: message.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn warn(name: &'static str, message: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            status: Status::Warn,
+            ..Self::fail(name, message, fix)
+        }
+    }
+}
+
+/// Runs checks against `/proc`, `/sys` and `/dev`, roots can be changed for tests
+pub struct Probe {
+    sysfs_root: PathBuf,
+    proc_root: PathBuf,
+    dev_root: PathBuf,
+    pin_dir: PathBuf,
+    definitions_dir: Option<PathBuf>,
+    load_program: bool,
+}
+
+impl Default for Probe {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Probe {
+    pub fn new() -> Self {
+        Self {
+            sysfs_root: PathBuf::from(DEFAULT_SYSFS_ROOT),
+            proc_root: PathBuf::from("/proc"),
+            dev_root: PathBuf::from("/dev"),
+            pin_dir: PathBuf::from(DEFAULT_PIN_DIR),
+            definitions_dir: None,
+            load_program: true,
+        }
+    }
+
+    pub fn set_sysfs_root(&mut self, path: impl Into<PathBuf>) {
+        self.sysfs_root = path.into();
+    }
+
+    pub fn set_proc_root(&mut self, path: impl Into<PathBuf>) {
+        self.proc_root = path.into();
+    }
+
+    pub fn set_dev_root(&mut self, path: impl Into<PathBuf>) {
+        self.dev_root = path.into();
+    }
+
+    /// Directory where `a4keyboard pin` keeps the program
+    pub fn set_pin_dir(&mut self, path: impl Into<PathBuf>) {
+        self.pin_dir = path.into();
+    }
+
+    /// Directory with device definitions which are checked, they aren't checked by default
+    pub fn set_definitions_dir(&mut self, path: impl Into<PathBuf>) {
+        self.definitions_dir = Some(path.into());
+    }
+
+    /// Whether HID-BPF program is loaded at the end if other checks are passed
+    pub fn set_load_program(&mut self, value: bool) {
+        self.load_program = value;
+    }
+
+    /// Checks every prerequisite in order, `devices` are used for finding keyboards
+    pub fn run(&self, devices: &Devices) -> Vec<Check> {
+        let mut checks = vec![
+            self.kernel_version(),
+            self.btf(),
+            self.kfunc(
+                "hid_bpf_allocate_context",
+                Status::Fail,
+                "kernel must be built with CONFIG_HID_BPF=y",
+            ),
+            self.kfunc(
+                "hid_bpf_hw_output_report",
+                Status::Warn,
+                "interrupt output reports through HID-BPF and `events` need Linux 6.11",
+            ),
+            self.permissions(),
+        ];
+
+        let bpf_ready = checks.iter().all(|check| check.status != Status::Fail);
+        if self.load_program && bpf_ready {
+            checks.push(load_program());
+        }
+
+        checks.extend(self.definitions());
+        checks.extend(self.devices(devices));
+        checks
+    }
+
+    fn definitions(&self) -> Option<Check> {
+        const NAME: &str = "definitions";
+
+        let dir = self.definitions_dir.as_ref()?;
+        let check = match definition::load_dir(dir) {
+            Ok(definitions) => Check::pass(
+                NAME,
+                format!("{} loaded from {}", definitions.len(), dir.display()),
+            ),
+            Err(err) => Check::fail(
+                NAME,
+                err.to_string(),
+                "fix or remove the file, only built-in drivers are used until then",
+            ),
+        };
+
+        Some(check)
+    }
+
+    fn kernel_version(&self) -> Check {
+        const NAME: &str = "kernel version";
+
+        let path = self.proc_root.join("sys/kernel/osrelease");
+        let release = match fs::read_to_string(&path) {
+            Ok(release) => release.trim().to_owned(),
+            Err(err) => {
+                return Check::warn(
+                    NAME,
+                    format!("{}: {err}", path.display()),
+                    "check that /proc is mounted",
+                )
+            }
+        };
+
+        let mut numbers = release
+            .split(|c: char| !c.is_ascii_digit())
+            .map(|number| number.parse::<u32>().ok());
+        let version = numbers.next().flatten().zip(numbers.next().flatten());
+
+        match version {
+            Some(version) if version >= MIN_KERNEL => Check::pass(NAME, release),
+            Some(_) => Check::fail(
+                NAME,
+                format!("{release} doesn't support HID-BPF syscall programs"),
+                format!(
+                    "upgrade to Linux {}.{} or newer, or use `--transport hidraw`",
+                    MIN_KERNEL.0, MIN_KERNEL.1
+                ),
+            ),
+            None => Check::warn(
+                NAME,
+                format!("unknown release `{release}`"),
+                "HID-BPF needs Linux 6.3 or newer",
+            ),
+        }
+    }
+
+    fn btf(&self) -> Check {
+        const NAME: &str = "BTF";
+
+        let path = self.sysfs_root.join("kernel/btf/vmlinux");
+        if path.exists() {
+            Check::pass(NAME, path.display().to_string())
+        } else {
+            Check::fail(
+                NAME,
+                format!("{} is missing", path.display()),
+                "kernel must be built with CONFIG_DEBUG_INFO_BTF=y",
+            )
+        }
+    }
+
+    /// Looks up kernel function in `/proc/kallsyms`, names are visible without root
+    fn kfunc(&self, name: &str, missing: Status, fix: &str) -> Check {
+        const NAME: &str = "HID-BPF kfunc";
+
+        let path = self.proc_root.join("kallsyms");
+        let symbols = match fs::read_to_string(&path) {
+            Ok(symbols) => symbols,
+            Err(err) => {
+                return Check::warn(
+                    NAME,
+                    format!("{}: {err}", path.display()),
+                    "check that /proc is mounted",
+                )
+            }
+        };
+
+        // ADDRESS TYPE NAME [MODULE]
+        let found = symbols
+            .lines()
+            .any(|line| line.split_whitespace().nth(2) == Some(name));
+        if found {
+            return Check::pass(NAME, format!("{name} is available"));
+        }
+
+        Check {
+            status: missing,
+            ..Check::fail(NAME, format!("{name} isn't found"), fix)
+        }
+    }
+
+    fn permissions(&self) -> Check {
+        const NAME: &str = "permissions";
+
+        let capabilities = fs::read_to_string(self.proc_root.join("self/status"))
+            .ok()
+            .and_then(|status| {
+                let line = status.lines().find(|line| line.starts_with("CapEff:"))?;
+                u64::from_str_radix(line["CapEff:".len()..].trim(), 16).ok()
+            })
+            .unwrap_or_default();
+
+        if capabilities & (1 << CAP_BPF) != 0 {
+            return Check::pass(NAME, "CAP_BPF");
+        }
+        if capabilities & (1 << CAP_SYS_ADMIN) != 0 {
+            return Check::pass(NAME, "CAP_SYS_ADMIN");
+        }
+
+        // without capabilities even pinned program can't be run when bpf() is disabled
+        let path = self.proc_root.join("sys/kernel/unprivileged_bpf_disabled");
+        let disabled = fs::read_to_string(&path)
+            .ok()
+            .and_then(|value| value.trim().parse::<u32>().ok())
+            .unwrap_or_default();
+        if disabled != 0 {
+            return Check::fail(
+                NAME,
+                format!("unprivileged bpf() is disabled by {}", path.display()),
+                "run as root, or allow pinned program with `sudo sysctl kernel.unprivileged_bpf_disabled=0` \
+                 (value 1 can be changed only at boot, in /etc/sysctl.d)",
+            );
+        }
+
+        let pinned = [PINNED_PROGRAM, PINNED_MAP]
+            .iter()
+            .all(|name| is_accessible(&self.pin_dir.join(name)));
+        if pinned {
+            return Check::pass(
+                NAME,
+                format!("program pinned in {} is accessible", self.pin_dir.display()),
+            );
+        }
+
+        Check::fail(
+            NAME,
+            "loading HID-BPF program needs CAP_BPF",
+            "run as root, or pin the program once with `sudo a4keyboard pin` and grant access to it",
+        )
+    }
+
+    fn devices(&self, devices: &Devices) -> Vec<Check> {
+        let mut supported = Vec::new();
+        let result = devices.for_each_devices(|info| {
+            if let Some(driver) = devices.driver_name(info) {
+                let hidraw = fs::read_dir(info.sysfs_path().join("hidraw"))
+                    .ok()
+                    .and_then(|mut dir| dir.next()?.ok())
+                    .map(|entry| self.dev_root.join(entry.file_name()));
+                supported.push((info.sysfs_name().to_owned(), driver.to_owned(), hidraw));
+            }
+            Ok::<(), Error>(())
+        });
+
+        if let Err(err) = result {
+            return vec![Check::fail(
+                "devices",
+                err.to_string(),
+                "check that sysfs is mounted",
+            )];
+        }
+
+        supported.sort();
+        if supported.is_empty() {
+            return vec![Check::fail(
+                "devices",
+                "no supported keyboards are found",
+                "check `a4keyboard list` and add a device definition for your model",
+            )];
+        }
+
+        let mut checks = Vec::new();
+        for (name, driver, hidraw) in supported {
+            checks.push(Check::pass("devices", format!("{name} ({driver})")));
+
+            let check = match hidraw {
+                Some(path) if is_accessible(&path) => {
+                    Check::pass("hidraw", format!("{} is accessible", path.display()))
+                }
+                Some(path) => Check::warn(
+                    "hidraw",
+                    format!("{} isn't accessible", path.display()),
+                    "needed only without HID-BPF: run as root or add udev rule \
+                     `KERNEL==\"hidraw*\", ATTRS{idVendor}==\"09da\", GROUP=\"input\", MODE=\"0660\"`",
+                ),
+                None => Check::warn(
+                    "hidraw",
+                    format!("{name} has no hidraw node"),
+                    "needed only without HID-BPF: load `hidraw` module",
+                ),
+            };
+            checks.push(check);
+        }
+
+        checks
+    }
+}
+
+fn load_program() -> Check {
+    const NAME: &str = "HID-BPF program";
+
+    match BpfTransport::new() {
+        Ok(_) => Check::pass(NAME, "loaded"),
+        Err(err) => Check::fail(
+            NAME,
+            err.to_string(),
+            "see libbpf messages above, or use `--transport hidraw`",
+        ),
+    }
+}
+
+fn is_accessible(path: &Path) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+
+    // SAFETY: `path` is valid C string
+    unsafe { libc::access(path.as_ptr(), libc::R_OK | libc::W_OK) == 0 }
+}
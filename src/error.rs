@@ -0,0 +1,108 @@
+use crate::devices::definition::DefinitionError;
+use crate::devices::transport::capture::CaptureError;
+use crate::usbmon::UsbmonError;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// HID-BPF program can't be loaded or run
+    #[error("HID-BPF: {0}")]
+    Bpf(libbpf_rs::Error),
+
+    /// Report can't be delivered to device
+    #[error("HID {hid:04X}: failed to send report: {source}")]
+    Transport {
+        hid: u16,
+        #[source]
+        source: io::Error,
+    },
+
+    /// Report is larger than transport can deliver
+    #[error("report of {size} bytes is larger than {max} bytes")]
+    ReportTooBig { size: usize, max: usize },
+
+    /// Devices can't be listed in sysfs
+    #[error("{}: {source}", path.display())]
+    Enumeration {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// Report descriptor of device is malformed
+    #[error("{device}: bad report descriptor: {source}")]
+    Descriptor {
+        device: String,
+        #[source]
+        source: hrd::Error,
+    },
+
+    /// Device definition file is malformed
+    #[error("{}: {source}", path.display())]
+    Definition {
+        path: PathBuf,
+        #[source]
+        source: DefinitionError,
+    },
+
+    /// Capture file can't be read
+    #[error("{}: {source}", path.display())]
+    Capture {
+        path: PathBuf,
+        #[source]
+        source: CaptureError,
+    },
+
+    /// usbmon capture can't be read
+    #[error("{}: {source}", path.display())]
+    Usbmon {
+        path: PathBuf,
+        #[source]
+        source: UsbmonError,
+    },
+
+    /// Access to device node or BPF is denied
+    #[error("{what}: permission denied, run as root or grant access to the device")]
+    PermissionDenied {
+        what: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    /// File or directory can't be accessed
+    #[error("{}: {source}", path.display())]
+    File {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// None of selected devices is supported by any driver
+    #[error("no supported devices are found, check `list` output and `--device` selectors")]
+    NoSupportedDevices,
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl From<libbpf_rs::Error> for Error {
+    fn from(err: libbpf_rs::Error) -> Self {
+        match err.kind() {
+            libbpf_rs::ErrorKind::PermissionDenied => Error::PermissionDenied {
+                what: "HID-BPF".to_owned(),
+                source: err.into(),
+            },
+            _ => Error::Bpf(err),
+        }
+    }
+}
+
+impl Error {
+    /// Returns function which adds `path` to I/O error, for `map_err`
+    pub(crate) fn file(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.into();
+        move |source| Error::File { path, source }
+    }
+}
@@ -0,0 +1,516 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+pub const KEYS_COUNT: usize = 104;
+
+/// Physical arrangement of keys
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Variant {
+    /// US layout with wide left shift and single-row enter
+    #[default]
+    Ansi,
+    /// European layout with tall enter, `#` key left of it and `<>` key right of left shift
+    Iso,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Key {
+    pub name: &'static str,
+    /// Usage ID on Keyboard/Keypad page (0x07), `None` for keys which are handled by firmware
+    pub usage: Option<u16>,
+    pub row: u8,
+    pub col: u8,
+    /// Horizontal position of key center in key units (width of alphanumeric key)
+    pub x: f32,
+    /// Vertical position of key center in key units
+    pub y: f32,
+}
+
+/// Maps indices of colors in frame to keys
+///
+/// Frame is passed to [`DeviceHandle::set_colors`](crate::devices::DeviceHandle::set_colors).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    variant: Variant,
+    keys: Vec<Option<Key>>,
+}
+
+/// Layout file, maps indices in frame to usage IDs on Keyboard/Keypad page, or to names of
+/// keys which have no usage ID
+///
+/// ```toml
+/// variant = "ansi"
+///
+/// [keys]
+/// 0 = 0x29
+/// 1 = 0x3A
+/// 88 = "fn"
+/// ```
+#[derive(Deserialize)]
+struct LayoutFile {
+    variant: Variant,
+    keys: BTreeMap<String, KeyEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeyEntry {
+    Usage(u16),
+    Name(String),
+}
+
+/// Key names in order of colors in frame
+#[rustfmt::skip]
+const FRAME: [&str; KEYS_COUNT] = [
+    "esc", "f1", "f2", "f3", "f4", "f5", "f6", "f7", "f8", "f9", "f10", "f11", "f12",
+    "prtsc", "scrlk", "pause",
+
+    "grave", "1", "2", "3", "4", "5", "6", "7", "8", "9", "0", "minus", "equal", "backspace",
+    "insert", "home", "pgup", "numlock", "kpslash", "kpasterisk", "kpminus",
+
+    "tab", "q", "w", "e", "r", "t", "y", "u", "i", "o", "p", "lbracket", "rbracket", "backslash",
+    "delete", "end", "pgdn", "kp7", "kp8", "kp9", "kpplus",
+
+    "capslock", "a", "s", "d", "f", "g", "h", "j", "k", "l", "semicolon", "apostrophe", "enter",
+    "kp4", "kp5", "kp6",
+
+    "lshift", "z", "x", "c", "v", "b", "n", "m", "comma", "dot", "slash", "rshift",
+    "up", "kp1", "kp2", "kp3", "kpenter",
+
+    "lctrl", "lwin", "lalt", "space", "ralt", "fn", "menu", "rctrl",
+    "left", "down", "right", "kp0", "kpdot",
+];
+
+/// Index of key which is `backslash` on ANSI and `nonushash` on ISO keyboards
+const BACKSLASH_INDEX: usize = 50;
+
+#[rustfmt::skip]
+const USAGES: &[(&str, u16)] = &[
+    ("a", 0x04), ("b", 0x05), ("c", 0x06), ("d", 0x07), ("e", 0x08), ("f", 0x09), ("g", 0x0A),
+    ("h", 0x0B), ("i", 0x0C), ("j", 0x0D), ("k", 0x0E), ("l", 0x0F), ("m", 0x10), ("n", 0x11),
+    ("o", 0x12), ("p", 0x13), ("q", 0x14), ("r", 0x15), ("s", 0x16), ("t", 0x17), ("u", 0x18),
+    ("v", 0x19), ("w", 0x1A), ("x", 0x1B), ("y", 0x1C), ("z", 0x1D),
+    ("1", 0x1E), ("2", 0x1F), ("3", 0x20), ("4", 0x21), ("5", 0x22), ("6", 0x23), ("7", 0x24),
+    ("8", 0x25), ("9", 0x26), ("0", 0x27),
+    ("enter", 0x28), ("esc", 0x29), ("backspace", 0x2A), ("tab", 0x2B), ("space", 0x2C),
+    ("minus", 0x2D), ("equal", 0x2E), ("lbracket", 0x2F), ("rbracket", 0x30),
+    ("backslash", 0x31), ("nonushash", 0x32), ("semicolon", 0x33), ("apostrophe", 0x34),
+    ("grave", 0x35), ("comma", 0x36), ("dot", 0x37), ("slash", 0x38), ("capslock", 0x39),
+    ("f1", 0x3A), ("f2", 0x3B), ("f3", 0x3C), ("f4", 0x3D), ("f5", 0x3E), ("f6", 0x3F),
+    ("f7", 0x40), ("f8", 0x41), ("f9", 0x42), ("f10", 0x43), ("f11", 0x44), ("f12", 0x45),
+    ("prtsc", 0x46), ("scrlk", 0x47), ("pause", 0x48), ("insert", 0x49), ("home", 0x4A),
+    ("pgup", 0x4B), ("delete", 0x4C), ("end", 0x4D), ("pgdn", 0x4E),
+    ("right", 0x4F), ("left", 0x50), ("down", 0x51), ("up", 0x52),
+    ("numlock", 0x53), ("kpslash", 0x54), ("kpasterisk", 0x55), ("kpminus", 0x56),
+    ("kpplus", 0x57), ("kpenter", 0x58), ("kp1", 0x59), ("kp2", 0x5A), ("kp3", 0x5B),
+    ("kp4", 0x5C), ("kp5", 0x5D), ("kp6", 0x5E), ("kp7", 0x5F), ("kp8", 0x60), ("kp9", 0x61),
+    ("kp0", 0x62), ("kpdot", 0x63), ("nonusbackslash", 0x64), ("menu", 0x65),
+    ("lctrl", 0xE0), ("lshift", 0xE1), ("lalt", 0xE2), ("lwin", 0xE3),
+    ("rctrl", 0xE4), ("rshift", 0xE5), ("ralt", 0xE6),
+];
+
+/// Empty space between keys
+const GAP: &str = "";
+
+type Row = &'static [(&'static str, f32)];
+
+const ROW_TOPS: [f32; 6] = [0.0, 1.5, 2.5, 3.5, 4.5, 5.5];
+
+#[rustfmt::skip]
+const FUNCTION_ROW: Row = &[
+    ("esc", 1.0), (GAP, 1.0),
+    ("f1", 1.0), ("f2", 1.0), ("f3", 1.0), ("f4", 1.0), (GAP, 0.5),
+    ("f5", 1.0), ("f6", 1.0), ("f7", 1.0), ("f8", 1.0), (GAP, 0.5),
+    ("f9", 1.0), ("f10", 1.0), ("f11", 1.0), ("f12", 1.0), (GAP, 0.25),
+    ("prtsc", 1.0), ("scrlk", 1.0), ("pause", 1.0),
+];
+
+#[rustfmt::skip]
+const NUMBER_ROW: Row = &[
+    ("grave", 1.0), ("1", 1.0), ("2", 1.0), ("3", 1.0), ("4", 1.0), ("5", 1.0), ("6", 1.0),
+    ("7", 1.0), ("8", 1.0), ("9", 1.0), ("0", 1.0), ("minus", 1.0), ("equal", 1.0),
+    ("backspace", 2.0), (GAP, 0.25),
+    ("insert", 1.0), ("home", 1.0), ("pgup", 1.0), (GAP, 0.25),
+    ("numlock", 1.0), ("kpslash", 1.0), ("kpasterisk", 1.0), ("kpminus", 1.0),
+];
+
+#[rustfmt::skip]
+const BOTTOM_ROW: Row = &[
+    ("lctrl", 1.25), ("lwin", 1.25), ("lalt", 1.25), ("space", 6.25),
+    ("ralt", 1.25), ("fn", 1.25), ("menu", 1.25), ("rctrl", 1.25), (GAP, 0.25),
+    ("left", 1.0), ("down", 1.0), ("right", 1.0), (GAP, 0.25),
+    ("kp0", 2.0), ("kpdot", 1.0),
+];
+
+#[rustfmt::skip]
+const ANSI_ROWS: [Row; 6] = [
+    FUNCTION_ROW,
+    NUMBER_ROW,
+    &[
+        ("tab", 1.5), ("q", 1.0), ("w", 1.0), ("e", 1.0), ("r", 1.0), ("t", 1.0), ("y", 1.0),
+        ("u", 1.0), ("i", 1.0), ("o", 1.0), ("p", 1.0), ("lbracket", 1.0), ("rbracket", 1.0),
+        ("backslash", 1.5), (GAP, 0.25),
+        ("delete", 1.0), ("end", 1.0), ("pgdn", 1.0), (GAP, 0.25),
+        ("kp7", 1.0), ("kp8", 1.0), ("kp9", 1.0), ("kpplus", 1.0),
+    ],
+    &[
+        ("capslock", 1.75), ("a", 1.0), ("s", 1.0), ("d", 1.0), ("f", 1.0), ("g", 1.0),
+        ("h", 1.0), ("j", 1.0), ("k", 1.0), ("l", 1.0), ("semicolon", 1.0), ("apostrophe", 1.0),
+        ("enter", 2.25), (GAP, 3.5),
+        ("kp4", 1.0), ("kp5", 1.0), ("kp6", 1.0),
+    ],
+    &[
+        ("lshift", 2.25), ("z", 1.0), ("x", 1.0), ("c", 1.0), ("v", 1.0), ("b", 1.0),
+        ("n", 1.0), ("m", 1.0), ("comma", 1.0), ("dot", 1.0), ("slash", 1.0),
+        ("rshift", 2.75), (GAP, 1.25),
+        ("up", 1.0), (GAP, 1.25),
+        ("kp1", 1.0), ("kp2", 1.0), ("kp3", 1.0), ("kpenter", 1.0),
+    ],
+    BOTTOM_ROW,
+];
+
+#[rustfmt::skip]
+const ISO_ROWS: [Row; 6] = [
+    FUNCTION_ROW,
+    NUMBER_ROW,
+    &[
+        ("tab", 1.5), ("q", 1.0), ("w", 1.0), ("e", 1.0), ("r", 1.0), ("t", 1.0), ("y", 1.0),
+        ("u", 1.0), ("i", 1.0), ("o", 1.0), ("p", 1.0), ("lbracket", 1.0), ("rbracket", 1.0),
+        ("enter", 1.5), (GAP, 0.25),
+        ("delete", 1.0), ("end", 1.0), ("pgdn", 1.0), (GAP, 0.25),
+        ("kp7", 1.0), ("kp8", 1.0), ("kp9", 1.0), ("kpplus", 1.0),
+    ],
+    &[
+        ("capslock", 1.75), ("a", 1.0), ("s", 1.0), ("d", 1.0), ("f", 1.0), ("g", 1.0),
+        ("h", 1.0), ("j", 1.0), ("k", 1.0), ("l", 1.0), ("semicolon", 1.0), ("apostrophe", 1.0),
+        ("nonushash", 1.0), (GAP, 4.75),
+        ("kp4", 1.0), ("kp5", 1.0), ("kp6", 1.0),
+    ],
+    &[
+        ("lshift", 1.25), ("nonusbackslash", 1.0), ("z", 1.0), ("x", 1.0), ("c", 1.0),
+        ("v", 1.0), ("b", 1.0), ("n", 1.0), ("m", 1.0), ("comma", 1.0), ("dot", 1.0),
+        ("slash", 1.0), ("rshift", 2.75), (GAP, 1.25),
+        ("up", 1.0), (GAP, 1.25),
+        ("kp1", 1.0), ("kp2", 1.0), ("kp3", 1.0), ("kpenter", 1.0),
+    ],
+    BOTTOM_ROW,
+];
+
+fn usage_of(name: &str) -> Option<u16> {
+    USAGES
+        .iter()
+        .find(|(key, _)| *key == name)
+        .map(|&(_, usage)| usage)
+}
+
+/// Returns true for keys which take two rows
+fn is_tall(variant: Variant, name: &str) -> bool {
+    matches!(name, "kpplus" | "kpenter") || (variant == Variant::Iso && name == "enter")
+}
+
+impl Variant {
+    fn rows(self) -> &'static [Row; 6] {
+        match self {
+            Variant::Ansi => &ANSI_ROWS,
+            Variant::Iso => &ISO_ROWS,
+        }
+    }
+
+    /// Returns key with its position by name
+    pub fn key(self, name: &str) -> Option<Key> {
+        for (row, (keys, top)) in self.rows().iter().zip(ROW_TOPS).enumerate() {
+            let mut x = 0.0;
+            let mut col = 0;
+
+            for &(key, width) in keys.iter() {
+                if key == name {
+                    let height = if is_tall(self, key) { 2.0 } else { 1.0 };
+
+                    return Some(Key {
+                        name: key,
+                        usage: usage_of(key),
+                        row: row as u8,
+                        col,
+                        x: x + width / 2.0,
+                        y: top + height / 2.0,
+                    });
+                }
+
+                x += width;
+                if key != GAP {
+                    col += 1;
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns key with its position by usage ID on Keyboard/Keypad page
+    pub fn key_by_usage(self, usage: u16) -> Option<Key> {
+        let &(name, _) = USAGES.iter().find(|&&(_, u)| u == usage)?;
+        self.key(name)
+    }
+}
+
+impl Layout {
+    pub fn new(variant: Variant) -> Self {
+        let keys = FRAME
+            .iter()
+            .enumerate()
+            // frame has no slot for `nonusbackslash`, ISO keyboards light `nonushash` instead
+            // of `backslash`
+            .map(|(index, &name)| match (variant, index) {
+                (Variant::Iso, BACKSLASH_INDEX) => variant.key("nonushash"),
+                _ => variant.key(name),
+            })
+            .collect();
+
+        Self { variant, keys }
+    }
+
+    /// Creates layout from usage IDs of keys at each index, `None` for indices without LED
+    pub fn from_usages(variant: Variant, usages: &[Option<u16>; KEYS_COUNT]) -> Self {
+        let keys = usages
+            .iter()
+            .map(|usage| variant.key_by_usage((*usage)?))
+            .collect();
+
+        Self { variant, keys }
+    }
+
+    /// Loads layout file which is written by [`Layout::save`]
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let invalid_data = |err| io::Error::new(io::ErrorKind::InvalidData, err);
+
+        let file: LayoutFile = toml::from_str(&fs::read_to_string(path)?)
+            .map_err(|err| invalid_data(err.to_string()))?;
+
+        let mut layout = Self::from_usages(file.variant, &[None; KEYS_COUNT]);
+        for (index, entry) in file.keys {
+            let index = index
+                .parse::<usize>()
+                .ok()
+                .filter(|&index| index < KEYS_COUNT)
+                .ok_or_else(|| invalid_data(format!("incorrect index `{index}`")))?;
+
+            let key = match entry {
+                KeyEntry::Usage(usage) => file
+                    .variant
+                    .key_by_usage(usage)
+                    .ok_or_else(|| invalid_data(format!("unknown usage {usage:#04x}")))?,
+                KeyEntry::Name(name) => file
+                    .variant
+                    .key(&name)
+                    .ok_or_else(|| invalid_data(format!("unknown key `{name}`")))?,
+            };
+
+            layout.set_key(index, Some(key));
+        }
+
+        Ok(layout)
+    }
+
+    /// Writes layout file, keys without usage ID are saved by name
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let variant = match self.variant {
+            Variant::Ansi => "ansi",
+            Variant::Iso => "iso",
+        };
+
+        let mut data = format!("variant = \"{variant}\"\n\n[keys]\n");
+        for (index, key) in self.iter() {
+            let _ = match key.usage {
+                Some(usage) => writeln!(data, "{index} = {usage:#04X} # {}", key.name),
+                None => writeln!(data, "{index} = \"{}\"", key.name),
+            };
+        }
+
+        fs::write(path, data)
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// Returns key by its index in frame, `None` if there is no key with LED at this index
+    pub fn key(&self, index: usize) -> Option<&Key> {
+        self.keys.get(index)?.as_ref()
+    }
+
+    /// Replaces key at `index` in frame, `None` means that there is no LED at this index
+    pub fn set_key(&mut self, index: usize, key: Option<Key>) {
+        self.keys[index] = key;
+    }
+
+    /// Iterates over keys with their indices in frame
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Key)> {
+        self.keys
+            .iter()
+            .enumerate()
+            .filter_map(|(index, key)| Some((index, key.as_ref()?)))
+    }
+
+    /// Returns index of key in frame by its name
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.iter()
+            .find(|(_, key)| key.name.eq_ignore_ascii_case(name))
+            .map(|(index, _)| index)
+    }
+
+    /// Returns index of key in frame by its usage ID on Keyboard/Keypad page
+    pub fn index_of_usage(&self, usage: u16) -> Option<usize> {
+        self.iter()
+            .find(|(_, key)| key.usage == Some(usage))
+            .map(|(index, _)| index)
+    }
+
+    fn range_of(&self, item: &str) -> Result<RangeInclusive<usize>, String> {
+        let index = |name: &str| {
+            self.index_of(name)
+                .ok_or_else(|| format!("unknown key `{name}`"))
+        };
+
+        match item.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (index(first)?, index(last)?);
+                if first > last {
+                    return Err(format!("incorrect range `{item}`"));
+                }
+
+                Ok(first..=last)
+            }
+            None => {
+                let index = index(item)?;
+                Ok(index..=index)
+            }
+        }
+    }
+
+    /// Parses comma separated list of key names and ranges (like `w,a,s,d` or `f1-f12`)
+    /// into indices in frame
+    pub fn parse_keys(&self, spec: &str) -> Result<Vec<usize>, String> {
+        let mut indices = Vec::new();
+        for item in spec.split(',') {
+            indices.extend(self.range_of(item.trim())?);
+        }
+
+        Ok(indices)
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self::new(Variant::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Layout;
+    use super::Variant;
+    use super::KEYS_COUNT;
+
+    #[test]
+    fn parse() {
+        let layout = Layout::default();
+
+        assert_eq!(layout.parse_keys("esc"), Ok(vec![0]));
+        assert_eq!(layout.parse_keys("ESC"), Ok(vec![0]));
+        assert_eq!(layout.parse_keys("f1-f12"), Ok(Vec::from_iter(1..=12)));
+        assert_eq!(layout.parse_keys("w,a,s,d"), Ok(vec![39, 59, 60, 61]));
+        assert_eq!(layout.parse_keys("1-3,kpdot"), Ok(vec![17, 18, 19, 103]));
+
+        assert!(layout.parse_keys("f12-f1").is_err());
+        assert!(layout.parse_keys("esc,").is_err());
+        assert!(layout.parse_keys("nokey").is_err());
+        assert!(layout.parse_keys("nonushash").is_err());
+    }
+
+    #[test]
+    fn every_index_has_key() {
+        for variant in [Variant::Ansi, Variant::Iso] {
+            let layout = Layout::new(variant);
+
+            assert_eq!(layout.iter().count(), KEYS_COUNT);
+            for (index, key) in layout.iter() {
+                assert_eq!(layout.index_of(key.name), Some(index), "{key:?}");
+                if let Some(usage) = key.usage {
+                    assert_eq!(layout.index_of_usage(usage), Some(index), "{key:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path =
+            std::env::temp_dir().join(format!("a4keyboard-layout-{}.toml", std::process::id()));
+
+        let mut usages = [None; KEYS_COUNT];
+        usages[0] = Some(0x29);
+        usages[50] = Some(0x32);
+        usages[103] = Some(0x63);
+
+        let mut layout = Layout::from_usages(Variant::Iso, &usages);
+        layout.set_key(88, Variant::Iso.key("fn"));
+        layout.save(&path).unwrap();
+        let loaded = Layout::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, layout);
+        assert_eq!(loaded.index_of("esc"), Some(0));
+        assert_eq!(loaded.index_of("nonushash"), Some(50));
+        assert_eq!(loaded.index_of("kpdot"), Some(103));
+        assert_eq!(loaded.index_of("fn"), Some(88));
+        assert_eq!(loaded.iter().count(), 4);
+    }
+
+    #[test]
+    fn keys() {
+        let ansi = Layout::new(Variant::Ansi);
+        let iso = Layout::new(Variant::Iso);
+
+        let key = |layout: &Layout, name| *layout.key(layout.index_of(name).unwrap()).unwrap();
+
+        let esc = key(&ansi, "esc");
+        assert_eq!(
+            (esc.usage, esc.row, esc.col, esc.x, esc.y),
+            (Some(0x29), 0, 0, 0.5, 0.5)
+        );
+
+        let w = key(&ansi, "w");
+        assert_eq!(
+            (w.usage, w.row, w.col, w.x, w.y),
+            (Some(0x1A), 2, 2, 3.0, 3.0)
+        );
+
+        let kpenter = key(&ansi, "kpenter");
+        assert_eq!((kpenter.row, kpenter.x, kpenter.y), (4, 22.0, 5.5));
+
+        let fn_key = key(&ansi, "fn");
+        assert_eq!((fn_key.usage, fn_key.row, fn_key.x), (None, 5, 11.875));
+
+        let kpdot = key(&ansi, "kpdot");
+        assert_eq!((kpdot.x, kpdot.y), (21.0, 6.0));
+
+        assert_eq!(ansi.index_of("backslash"), Some(50));
+        assert_eq!(iso.index_of("nonushash"), Some(50));
+        assert_eq!(iso.index_of("backslash"), None);
+
+        let enter = key(&iso, "enter");
+        assert_eq!((enter.row, enter.x, enter.y), (2, 14.25, 3.5));
+
+        let nonushash = key(&iso, "nonushash");
+        assert_eq!(
+            (nonushash.usage, nonushash.row, nonushash.x),
+            (Some(0x32), 3, 13.25)
+        );
+    }
+}
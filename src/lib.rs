@@ -1,5 +1,9 @@
-pub use libbpf_rs::Error;
+pub use error::Error;
 
 pub mod color;
 pub mod devices;
+pub mod doctor;
+mod error;
+pub mod layout;
+pub mod usbmon;
 pub mod utils;
@@ -0,0 +1,579 @@
+//! HID SET_REPORT requests from usbmon captures
+//!
+//! Supports text output of `/sys/kernel/debug/usb/usbmon/Nu` and pcap files written by
+//! Wireshark or tcpdump from `usbmonN` interfaces. Text output keeps only first 32 bytes
+//! of data, so pcap is preferred for longer reports.
+
+use crate::Error;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const PCAPNG_MAGIC: u32 = 0x0a0d_0d0a;
+/// Largest snapshot length of tcpdump, used when file doesn't set it
+const MAX_SNAPLEN: u32 = 262_144;
+
+/// `LINKTYPE_USB_LINUX` with 48 bytes header
+const LINKTYPE_USB_LINUX: u32 = 189;
+/// `LINKTYPE_USB_LINUX_MMAPPED` with 64 bytes header
+const LINKTYPE_USB_LINUX_MMAPPED: u32 = 220;
+
+const TRANSFER_CONTROL: u8 = 2;
+
+const REQUEST_TYPE_SET_REPORT: u8 = 0x21;
+const REQUEST_SET_REPORT: u8 = 0x09;
+const REQUEST_TYPE_GET_DESCRIPTOR: u8 = 0x80;
+const REQUEST_GET_DESCRIPTOR: u8 = 0x06;
+const DESCRIPTOR_DEVICE: u8 = 0x01;
+
+#[derive(Debug, thiserror::Error)]
+pub enum UsbmonError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("line {0}: unknown usbmon text format")]
+    Line(usize),
+    #[error("not a pcap file")]
+    Magic,
+    #[error("pcapng isn't supported, save capture as pcap")]
+    Pcapng,
+    #[error("unsupported pcap link type {0}, expected usbmon capture")]
+    LinkType(u32),
+    #[error("packet {0}: truncated usbmon header")]
+    Packet(usize),
+    #[error("packet {packet}: size {size} is larger than snapshot length {max}")]
+    PacketSize { packet: usize, size: u32, max: u32 },
+}
+
+/// `VID:PID` of USB device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UsbId {
+    pub vid: u16,
+    pub pid: u16,
+}
+
+impl FromStr for UsbId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |value| u16::from_str_radix(value, 16).ok();
+
+        s.split_once(':')
+            .and_then(|(vid, pid)| {
+                Some(Self {
+                    vid: parse(vid)?,
+                    pid: parse(pid)?,
+                })
+            })
+            .ok_or_else(|| format!("incorrect id `{s}`, expected `VID:PID` like `09da:fa10`"))
+    }
+}
+
+impl fmt::Display for UsbId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vid, self.pid)
+    }
+}
+
+/// Bus and device number, the same as in `lsusb` output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Address {
+    pub bus: u16,
+    pub device: u8,
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split_once(':')
+            .and_then(|(bus, device)| {
+                Some(Self {
+                    bus: bus.parse().ok()?,
+                    device: device.parse().ok()?,
+                })
+            })
+            .ok_or_else(|| format!("incorrect address `{s}`, expected `BUS:DEVICE` like `1:005`"))
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{:03}", self.bus, self.device)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportType {
+    Input,
+    Output,
+    Feature,
+    Unknown(u8),
+}
+
+impl ReportType {
+    fn from_raw(value: u8) -> Self {
+        match value {
+            1 => ReportType::Input,
+            2 => ReportType::Output,
+            3 => ReportType::Feature,
+            value => ReportType::Unknown(value),
+        }
+    }
+}
+
+impl fmt::Display for ReportType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReportType::Input => write!(f, "input"),
+            ReportType::Output => write!(f, "output"),
+            ReportType::Feature => write!(f, "feature"),
+            ReportType::Unknown(value) => write!(f, "type {value:#04x}"),
+        }
+    }
+}
+
+/// Report sent by host with SET_REPORT control transfer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetReport {
+    /// Time since first event of capture
+    pub time: Duration,
+    pub address: Address,
+    /// Known if capture contains enumeration of device
+    pub id: Option<UsbId>,
+    pub interface: u16,
+    pub report_type: ReportType,
+    pub report_id: u8,
+    /// Size of report, `data` is shorter if capture is truncated
+    pub length: usize,
+    pub data: Vec<u8>,
+}
+
+impl SetReport {
+    pub fn is_truncated(&self) -> bool {
+        self.data.len() < self.length
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EventKind {
+    Submit,
+    Complete,
+}
+
+/// URB event, the same in both formats
+struct Event {
+    urb: u64,
+    kind: EventKind,
+    time: Duration,
+    address: Address,
+    control: bool,
+    setup: Option<[u8; 8]>,
+    length: usize,
+    data: Vec<u8>,
+}
+
+/// Reads text or pcap capture depending on its content
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<SetReport>, Error> {
+    let path = path.as_ref();
+    let read = || {
+        let mut input = BufReader::new(File::open(path)?);
+        let magic = input.fill_buf()?.get(..4).map(|magic| {
+            let magic = magic.try_into().unwrap();
+            (u32::from_le_bytes(magic), u32::from_be_bytes(magic))
+        });
+
+        match magic {
+            Some((le, be))
+                if [le, be]
+                    .iter()
+                    .any(|magic| [PCAP_MAGIC_MICROS, PCAP_MAGIC_NANOS].contains(magic)) =>
+            {
+                read_pcap(input)
+            }
+            Some((PCAPNG_MAGIC, _)) => Err(UsbmonError::Pcapng),
+            _ => read_text(input),
+        }
+    };
+
+    read().map_err(|source| Error::Usbmon {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+/// Reads usbmon text format (`1u`), lines of other transfers than control are skipped
+pub fn read_text(input: impl BufRead) -> Result<Vec<SetReport>, UsbmonError> {
+    let mut events = Vec::new();
+    for (idx, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match parse_text_line(&line) {
+            Some(Some(event)) => events.push(event),
+            Some(None) => {}
+            None => return Err(UsbmonError::Line(idx + 1)),
+        }
+    }
+
+    Ok(set_reports(events))
+}
+
+/// Returns `Some(None)` for valid lines which aren't needed
+fn parse_text_line(line: &str) -> Option<Option<Event>> {
+    // URB_TAG TIMESTAMP EVENT ADDRESS STATUS|SETUP LENGTH [TAG DATA...]
+    let mut fields = line.split_whitespace();
+    let urb = u64::from_str_radix(fields.next()?, 16).ok()?;
+    let time = Duration::from_micros(fields.next()?.parse().ok()?);
+    let kind = match fields.next()? {
+        "S" => EventKind::Submit,
+        "C" => EventKind::Complete,
+        "E" => return Some(None),
+        _ => return None,
+    };
+
+    // like `Co:1:005:0`
+    let mut address = fields.next()?.split(':');
+    let control = address.next()?.starts_with('C');
+    let bus = address.next()?.parse().ok()?;
+    let device = address.next()?.parse().ok()?;
+    let address = Address { bus, device };
+    if !control {
+        return Some(None);
+    }
+
+    let mut setup = None;
+    let status = fields.next()?;
+    if status == "s" {
+        let mut bytes = [0u8; 8];
+        bytes[0] = u8::from_str_radix(fields.next()?, 16).ok()?;
+        bytes[1] = u8::from_str_radix(fields.next()?, 16).ok()?;
+        for word in bytes[2..].chunks_mut(2) {
+            let value = u16::from_str_radix(fields.next()?, 16).ok()?;
+            word.copy_from_slice(&value.to_le_bytes());
+        }
+        setup = Some(bytes);
+    }
+
+    let length = fields.next()?.parse().ok()?;
+    let mut data = Vec::new();
+    if fields.next() == Some("=") {
+        for word in fields {
+            if word.len() % 2 != 0 {
+                return None;
+            }
+            for i in (0..word.len()).step_by(2) {
+                data.push(u8::from_str_radix(word.get(i..i + 2)?, 16).ok()?);
+            }
+        }
+    }
+
+    Some(Some(Event {
+        urb,
+        kind,
+        time,
+        address,
+        control,
+        setup,
+        length,
+        data,
+    }))
+}
+
+/// Reads pcap file with `LINKTYPE_USB_LINUX` or `LINKTYPE_USB_LINUX_MMAPPED` packets
+pub fn read_pcap(mut input: impl Read) -> Result<Vec<SetReport>, UsbmonError> {
+    let mut header = [0u8; 24];
+    input.read_exact(&mut header)?;
+
+    let magic = [header[0], header[1], header[2], header[3]];
+    let little_endian = match u32::from_le_bytes(magic) {
+        PCAP_MAGIC_MICROS | PCAP_MAGIC_NANOS => true,
+        _ => match u32::from_be_bytes(magic) {
+            PCAP_MAGIC_MICROS | PCAP_MAGIC_NANOS => false,
+            _ => return Err(UsbmonError::Magic),
+        },
+    };
+    let u32_at = |data: &[u8], offset: usize| {
+        let bytes = data[offset..][..4].try_into().unwrap();
+        if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    };
+    let nanos = u32_at(&header, 0) == PCAP_MAGIC_NANOS;
+    // size of packets is read from file, so it's limited before allocation
+    let max_size = match u32_at(&header, 16) {
+        0 => MAX_SNAPLEN,
+        snaplen => snaplen.min(MAX_SNAPLEN),
+    };
+
+    let header_size = match u32_at(&header, 20) {
+        LINKTYPE_USB_LINUX => 48,
+        LINKTYPE_USB_LINUX_MMAPPED => 64,
+        link_type => return Err(UsbmonError::LinkType(link_type)),
+    };
+
+    let mut events = Vec::new();
+    let mut record = [0u8; 16];
+    for idx in 1.. {
+        match input.read_exact(&mut record) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        }
+
+        let seconds = u64::from(u32_at(&record, 0));
+        let fraction = u64::from(u32_at(&record, 4));
+        let time = if nanos {
+            Duration::from_secs(seconds) + Duration::from_nanos(fraction)
+        } else {
+            Duration::from_secs(seconds) + Duration::from_micros(fraction)
+        };
+
+        let size = u32_at(&record, 8);
+        if size > max_size {
+            return Err(UsbmonError::PacketSize {
+                packet: idx,
+                size,
+                max: max_size,
+            });
+        }
+
+        let mut packet = vec![0u8; size as usize];
+        input.read_exact(&mut packet)?;
+        if packet.len() < header_size {
+            return Err(UsbmonError::Packet(idx));
+        }
+
+        // struct usbmon_packet, in byte order of capturing host which is the same as file
+        let kind = match packet[8] {
+            b'S' => EventKind::Submit,
+            b'C' => EventKind::Complete,
+            _ => continue,
+        };
+        let bus = u16::from_le_bytes([packet[12], packet[13]]);
+        let bus = if little_endian { bus } else { bus.swap_bytes() };
+        let setup = (packet[14] == 0).then(|| packet[40..48].try_into().unwrap());
+
+        events.push(Event {
+            urb: u64::from_le_bytes(packet[..8].try_into().unwrap()),
+            kind,
+            time,
+            address: Address {
+                bus,
+                device: packet[11],
+            },
+            control: packet[9] == TRANSFER_CONTROL,
+            setup,
+            length: u32_at(&packet, 32) as usize,
+            data: packet[header_size..].to_vec(),
+        });
+    }
+
+    Ok(set_reports(events))
+}
+
+/// Collects SET_REPORT requests, devices are identified by their descriptors
+fn set_reports(events: Vec<Event>) -> Vec<SetReport> {
+    let start = events.first().map(|event| event.time).unwrap_or_default();
+
+    let mut ids = HashMap::new();
+    let mut descriptor_requests = HashMap::new();
+    let mut reports = Vec::new();
+
+    for event in events {
+        if !event.control {
+            continue;
+        }
+
+        match (event.kind, event.setup) {
+            (EventKind::Submit, Some(setup)) => {
+                let [request_type, request, value_low, value_high, index_low, index_high, length_low, length_high] =
+                    setup;
+
+                if request_type == REQUEST_TYPE_GET_DESCRIPTOR
+                    && request == REQUEST_GET_DESCRIPTOR
+                    && value_high == DESCRIPTOR_DEVICE
+                {
+                    descriptor_requests.insert(event.urb, event.address);
+                }
+
+                if request_type == REQUEST_TYPE_SET_REPORT && request == REQUEST_SET_REPORT {
+                    reports.push(SetReport {
+                        time: event.time.saturating_sub(start),
+                        address: event.address,
+                        id: ids.get(&event.address).copied(),
+                        interface: u16::from_le_bytes([index_low, index_high]),
+                        report_type: ReportType::from_raw(value_high),
+                        report_id: value_low,
+                        length: usize::from(u16::from_le_bytes([length_low, length_high]))
+                            .max(event.length),
+                        data: event.data,
+                    });
+                }
+            }
+            (EventKind::Complete, _) => {
+                let Some(address) = descriptor_requests.remove(&event.urb) else {
+                    continue;
+                };
+
+                // idVendor and idProduct of device descriptor
+                if let Some(id) = event.data.get(8..12) {
+                    let id = UsbId {
+                        vid: u16::from_le_bytes([id[0], id[1]]),
+                        pid: u16::from_le_bytes([id[2], id[3]]),
+                    };
+                    ids.insert(address, id);
+                }
+            }
+            (EventKind::Submit, None) => {}
+        }
+    }
+
+    reports
+}
+
+#[cfg(test)]
+mod test {
+    use super::read_pcap;
+    use super::read_text;
+    use super::Address;
+    use super::ReportType;
+    use super::UsbId;
+    use super::UsbmonError;
+    use std::time::Duration;
+
+    const BLOODY: UsbId = UsbId {
+        vid: 0x09da,
+        pid: 0xfa10,
+    };
+
+    #[test]
+    fn text() {
+        let capture = "\
+            ffff8881 3575914555 S Ci:1:005:0 s 80 06 0100 0000 0012 18 <\n\
+            ffff8881 3575914560 C Ci:1:005:0 0 18 = 12011001 00000008 da0910fa 00010102 0001\n\
+            ffff8882 3575915000 S Co:1:005:0 s 21 09 0307 0002 0040 64 = 07030601 00000000\n\
+            ffff8882 3575915100 C Co:1:005:0 0 64 >\n\
+            ffff8883 3575915200 S Ii:1:005:1 -115:1 8 <\n\
+            ffff8884 3575916000 S Co:1:006:0 s 21 09 0200 0000 0001 1 = 01\n";
+
+        let reports = read_text(capture.as_bytes()).unwrap();
+        assert_eq!(reports.len(), 2);
+
+        let report = &reports[0];
+        assert_eq!(report.time, Duration::from_micros(445));
+        assert_eq!(report.address, Address { bus: 1, device: 5 });
+        assert_eq!(report.id, Some(BLOODY));
+        assert_eq!(report.interface, 2);
+        assert_eq!(report.report_type, ReportType::Feature);
+        assert_eq!(report.report_id, 7);
+        assert_eq!(report.length, 64);
+        assert_eq!(report.data, [0x07, 0x03, 0x06, 0x01, 0, 0, 0, 0]);
+        assert!(report.is_truncated());
+
+        let report = &reports[1];
+        assert_eq!(report.id, None);
+        assert_eq!(report.report_type, ReportType::Output);
+        assert_eq!(report.data, [0x01]);
+        assert!(!report.is_truncated());
+
+        assert!(matches!(
+            read_text("ffff8881 1 S\n".as_bytes()),
+            Err(UsbmonError::Line(1))
+        ));
+    }
+
+    fn pcap_packet(urb: u64, kind: u8, setup: Option<[u8; 8]>, data: &[u8]) -> Vec<u8> {
+        let mut header = [0u8; 64];
+        header[..8].copy_from_slice(&urb.to_le_bytes());
+        header[8] = kind;
+        header[9] = 2;
+        header[11] = 5;
+        header[12..14].copy_from_slice(&1u16.to_le_bytes());
+        header[14] = if setup.is_some() { 0 } else { b'-' };
+        header[32..36].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        header[36..40].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        if let Some(setup) = setup {
+            header[40..48].copy_from_slice(&setup);
+        }
+
+        let mut record = Vec::new();
+        record.extend_from_slice(&2u32.to_le_bytes());
+        record.extend_from_slice(&(urb as u32 * 1000).to_le_bytes());
+        record.extend_from_slice(&((64 + data.len()) as u32).to_le_bytes());
+        record.extend_from_slice(&((64 + data.len()) as u32).to_le_bytes());
+        record.extend_from_slice(&header);
+        record.extend_from_slice(data);
+        record
+    }
+
+    #[test]
+    fn pcap() {
+        let mut capture = Vec::new();
+        capture.extend_from_slice(&0xa1b2_c3d4u32.to_le_bytes());
+        capture.extend_from_slice(&[2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        capture.extend_from_slice(&65535u32.to_le_bytes());
+        capture.extend_from_slice(&220u32.to_le_bytes());
+
+        let descriptor = [0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x12, 0x00];
+        let mut device = [0u8; 18];
+        device[8..12].copy_from_slice(&[0xda, 0x09, 0x10, 0xfa]);
+        capture.extend(pcap_packet(1, b'S', Some(descriptor), &[]));
+        capture.extend(pcap_packet(1, b'C', None, &device));
+
+        let set_report = [0x21, 0x09, 0x07, 0x03, 0x02, 0x00, 0x40, 0x00];
+        let mut packet = [0u8; 64];
+        packet[..4].copy_from_slice(&[0x07, 0x03, 0x06, 0x01]);
+        capture.extend(pcap_packet(3, b'S', Some(set_report), &packet));
+        capture.extend(pcap_packet(3, b'C', None, &[]));
+
+        let reports = read_pcap(capture.as_slice()).unwrap();
+        assert_eq!(reports.len(), 1);
+
+        let report = &reports[0];
+        assert_eq!(report.time, Duration::from_millis(2));
+        assert_eq!(report.id, Some(BLOODY));
+        assert_eq!(report.report_type, ReportType::Feature);
+        assert_eq!(report.data, packet);
+        assert!(!report.is_truncated());
+
+        capture[20] = 1;
+        assert!(matches!(
+            read_pcap(capture.as_slice()),
+            Err(UsbmonError::LinkType(1))
+        ));
+        assert!(matches!(read_pcap(&capture[4..]), Err(UsbmonError::Magic)));
+
+        // size of the first packet is larger than snapshot length
+        capture[20] = 220;
+        capture[32..36].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            read_pcap(capture.as_slice()),
+            Err(UsbmonError::PacketSize {
+                packet: 1,
+                size: u32::MAX,
+                max: 65535
+            })
+        ));
+    }
+
+    #[test]
+    fn parse_ids() {
+        assert_eq!("09da:FA10".parse(), Ok(BLOODY));
+        assert!("09da".parse::<UsbId>().is_err());
+        assert_eq!("1:005".parse(), Ok(Address { bus: 1, device: 5 }));
+        assert_eq!(Address { bus: 3, device: 12 }.to_string(), "3:012");
+    }
+}
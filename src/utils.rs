@@ -1,7 +1,9 @@
 use std::mem::size_of_val;
 use std::slice;
 
-/// SAFETY: must work only for simple types
+/// # Safety
+///
+/// must work only for simple types
 pub unsafe trait AsBytes {
     fn as_bytes(&self) -> &[u8];
     fn as_bytes_mut(&mut self) -> &mut [u8];
@@ -21,27 +23,10 @@ unsafe impl<T: Copy> AsBytes for T {
 /// SAFETY: buffer of `[T]` is simple type because `T` requires `Copy` trait
 unsafe impl<T: Copy> AsBytes for [T] {
     fn as_bytes(&self) -> &[u8] {
-        unsafe { slice::from_raw_parts(self.as_ptr() as *const T as *const u8, size_of_val(self)) }
+        unsafe { slice::from_raw_parts(self.as_ptr() as *const u8, size_of_val(self)) }
     }
 
     fn as_bytes_mut(&mut self) -> &mut [u8] {
-        unsafe {
-            slice::from_raw_parts_mut(self.as_mut_ptr() as *mut T as *mut u8, size_of_val(self))
-        }
+        unsafe { slice::from_raw_parts_mut(self.as_mut_ptr() as *mut u8, size_of_val(self)) }
     }
 }
-
-macro_rules! startup {
-    ($($tt:tt)*) => {
-        #[link_section = ".init_array"]
-        #[used]
-        static mut STARTUP: extern "C" fn() = {
-            extern "C" fn f()  {
-                $($tt)*
-            }
-            f
-        };
-    };
-}
-
-pub(crate) use startup;
@@ -0,0 +1,166 @@
+use a4keyboard::devices::transport::bpf::PINNED_MAP;
+use a4keyboard::devices::transport::bpf::PINNED_PROGRAM;
+use a4keyboard::devices::Devices;
+use a4keyboard::doctor::Probe;
+use a4keyboard::doctor::Status;
+use std::fs;
+
+const SYSFS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/sysfs");
+const PROC: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/proc");
+
+#[test]
+fn fixture_checks() {
+    let mut devices = Devices::new();
+    devices.set_sysfs_root(SYSFS);
+
+    let mut probe = Probe::new();
+    probe.set_sysfs_root(SYSFS);
+    probe.set_proc_root(PROC);
+    probe.set_dev_root(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/dev"));
+    probe.set_pin_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/pin"));
+    probe.set_load_program(false);
+
+    let checks = probe.run(&devices);
+    let summary = Vec::from_iter(
+        checks
+            .iter()
+            .map(|check| (check.name, check.status, check.message.as_str())),
+    );
+
+    assert_eq!(
+        summary,
+        [
+            ("kernel version", Status::Pass, "6.8.0-45-generic"),
+            (
+                "BTF",
+                Status::Pass,
+                concat!(
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/tests/fixtures/sysfs/kernel/btf/vmlinux"
+                )
+            ),
+            (
+                "HID-BPF kfunc",
+                Status::Pass,
+                "hid_bpf_allocate_context is available"
+            ),
+            (
+                "HID-BPF kfunc",
+                Status::Warn,
+                "hid_bpf_hw_output_report isn't found"
+            ),
+            (
+                "permissions",
+                Status::Fail,
+                "loading HID-BPF program needs CAP_BPF"
+            ),
+            ("devices", Status::Pass, "0003:09DA:FA10.0005 (bloody)"),
+            (
+                "hidraw",
+                Status::Warn,
+                "0003:09DA:FA10.0005 has no hidraw node"
+            ),
+            ("devices", Status::Pass, "0003:09DA:FA10.0009 (bloody)"),
+            (
+                "hidraw",
+                Status::Warn,
+                "0003:09DA:FA10.0009 has no hidraw node"
+            ),
+        ]
+    );
+    assert!(checks
+        .iter()
+        .filter(|check| check.status != Status::Pass)
+        .all(|check| check.fix.is_some()));
+}
+
+#[test]
+fn missing_features() {
+    let root = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/missing");
+
+    let mut devices = Devices::new();
+    devices.set_sysfs_root(root);
+
+    let mut probe = Probe::new();
+    probe.set_sysfs_root(root);
+    probe.set_proc_root(root);
+    probe.set_pin_dir(root);
+    probe.set_load_program(true);
+
+    // program isn't loaded when prerequisites fail
+    let checks = probe.run(&devices);
+    let statuses = Vec::from_iter(checks.iter().map(|check| (check.name, check.status)));
+    assert_eq!(
+        statuses,
+        [
+            ("kernel version", Status::Warn),
+            ("BTF", Status::Fail),
+            ("HID-BPF kfunc", Status::Warn),
+            ("HID-BPF kfunc", Status::Warn),
+            ("permissions", Status::Fail),
+            ("devices", Status::Fail),
+        ]
+    );
+}
+
+#[test]
+fn unprivileged_bpf_disabled() {
+    let root = std::env::temp_dir().join(format!("a4keyboard-doctor-{}", std::process::id()));
+    let pin_dir = root.join("pin");
+    fs::create_dir_all(root.join("sys/kernel")).unwrap();
+    fs::create_dir_all(&pin_dir).unwrap();
+    for name in [PINNED_PROGRAM, PINNED_MAP] {
+        fs::write(pin_dir.join(name), "").unwrap();
+    }
+
+    let mut devices = Devices::new();
+    devices.set_sysfs_root(SYSFS);
+
+    let mut probe = Probe::new();
+    probe.set_sysfs_root(SYSFS);
+    probe.set_proc_root(&root);
+    probe.set_pin_dir(&pin_dir);
+    probe.set_load_program(false);
+
+    let permissions = |probe: &Probe| {
+        let checks = probe.run(&devices);
+        let check = checks.into_iter().find(|check| check.name == "permissions");
+        check.map(|check| check.status)
+    };
+
+    // pinned program can be used by anyone
+    assert_eq!(permissions(&probe), Some(Status::Pass));
+
+    fs::write(root.join("sys/kernel/unprivileged_bpf_disabled"), "2\n").unwrap();
+    assert_eq!(permissions(&probe), Some(Status::Fail));
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn malformed_definition() {
+    let dir = std::env::temp_dir().join(format!("a4keyboard-definitions-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("broken.toml"), "name = ").unwrap();
+
+    let mut devices = Devices::new();
+    devices.set_sysfs_root(SYSFS);
+
+    let mut probe = Probe::new();
+    probe.set_sysfs_root(SYSFS);
+    probe.set_proc_root(PROC);
+    probe.set_definitions_dir(&dir);
+    probe.set_load_program(false);
+
+    // other checks are still run
+    let checks = probe.run(&devices);
+    let check = checks
+        .iter()
+        .find(|check| check.name == "definitions")
+        .unwrap();
+    assert_eq!(check.status, Status::Fail);
+    assert!(check.message.contains("broken.toml"));
+    assert_eq!(checks.last().unwrap().name, "hidraw");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,185 @@
+use a4keyboard::color::Color;
+use a4keyboard::devices::testing::Harness;
+use a4keyboard::devices::transport::Packet;
+use a4keyboard::devices::transport::RecordingTransport;
+use a4keyboard::devices::transport::ReportType;
+use a4keyboard::devices::Device;
+use a4keyboard::devices::DeviceInfo;
+use a4keyboard::devices::Devices;
+use a4keyboard::devices::Writer;
+use a4keyboard::Error;
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/sysfs");
+
+/// Driver which is defined outside of the crate, claims the mouse from fixture
+struct External;
+
+impl Device for External {
+    const NAME: &'static str = "external";
+
+    fn probe(info: &DeviceInfo) -> bool {
+        info.vid() == 0x046d && info.pid() == 0xc077
+    }
+
+    fn gain_control(writer: &mut Writer) -> Result<(), Error> {
+        writer.write(&[0x01, 0x01])
+    }
+
+    fn release_control(writer: &mut Writer) -> Result<(), Error> {
+        writer.write(&[0x01, 0x00])
+    }
+
+    fn set_colors(writer: &mut Writer, colors: &[Color; 104]) -> Result<(), Error> {
+        let Color { r, g, b } = colors[0];
+        writer.write(&[0x02, r, g, b])
+    }
+}
+
+#[test]
+fn register_external() {
+    let transport = RecordingTransport::new();
+
+    let mut devices = Devices::empty();
+    devices.register::<External>();
+    devices.use_transport(Box::new(transport.clone()));
+    devices.set_sysfs_root(FIXTURE);
+
+    let mut drivers = Vec::new();
+    devices
+        .for_each_devices(|info| {
+            drivers.push(devices.driver_name(info));
+            Ok::<(), Error>(())
+        })
+        .unwrap();
+    drivers.sort();
+    assert_eq!(drivers, [None, None, None, None, Some("external")]);
+
+    let control = devices.gain_control().unwrap();
+    assert_eq!(
+        transport.take_packets(),
+        [Packet {
+            hid: 0x0001,
+            report_type: ReportType::Feature,
+            data: vec![0x01, 0x01]
+        }]
+    );
+
+    drop(control);
+    assert_eq!(
+        transport.take_packets(),
+        [Packet {
+            hid: 0x0001,
+            report_type: ReportType::Feature,
+            data: vec![0x01, 0x00]
+        }]
+    );
+}
+
+#[test]
+fn harness() {
+    let info = |vid, pid| DeviceInfo::new(0x0007, vid, pid, hrd::parse(&[]).unwrap());
+
+    assert!(Harness::probe_driver::<External>(&info(0x09da, 0xfa10)).is_none());
+
+    let mut harness = Harness::probe_driver::<External>(&info(0x046d, 0xc077)).unwrap();
+
+    let red = Color {
+        r: 0xff,
+        g: 0,
+        b: 0,
+    };
+    assert_eq!(
+        harness.set_colors(&[red; 104]).unwrap(),
+        [Packet {
+            hid: 0x0007,
+            report_type: ReportType::Feature,
+            data: vec![0x02, 0xff, 0x00, 0x00]
+        }]
+    );
+}
+
+#[test]
+fn load_definitions() {
+    let mut devices = Devices::empty();
+    devices.set_sysfs_root(FIXTURE);
+
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/definitions");
+    assert_eq!(devices.load_definitions(dir).unwrap(), 1);
+    assert_eq!(devices.load_definitions("/nonexistent").unwrap(), 0);
+
+    let mut drivers = Vec::new();
+    devices
+        .for_each_devices(|info| {
+            if let Some(driver) = devices.driver_name(info) {
+                drivers.push((info.hid(), driver.to_owned()));
+            }
+            Ok::<(), Error>(())
+        })
+        .unwrap();
+    drivers.sort();
+    assert_eq!(
+        drivers,
+        [
+            (0x0005, "bloody-b820r".to_owned()),
+            (0x0009, "bloody-b820r".to_owned())
+        ]
+    );
+}
+
+#[test]
+fn builtin_takes_precedence() {
+    let transport = RecordingTransport::new();
+
+    let mut devices = Devices::new();
+    devices.use_transport(Box::new(transport.clone()));
+    devices.set_sysfs_root(FIXTURE);
+    devices
+        .load_definitions(concat!(env!("CARGO_MANIFEST_DIR"), "/definitions"))
+        .unwrap();
+
+    let mut drivers = Vec::new();
+    devices
+        .for_each_supported_devices(|dev| {
+            drivers.push(dev.driver_name().to_owned());
+            dev.gain_control()
+        })
+        .unwrap();
+    drivers.sort();
+    assert_eq!(drivers, ["bloody", "bloody"]);
+
+    // definition sends the same reports, so they would be doubled
+    let mut hids = Vec::from_iter(transport.take_packets().iter().map(|packet| packet.hid));
+    let count = hids.len();
+    hids.dedup();
+    assert_eq!(hids.len(), 2);
+    assert_eq!(count, 4);
+}
+
+#[test]
+fn read_report() {
+    let transport = RecordingTransport::new();
+    transport.set_report(&[0x05, 0x01, 0x02, 0x03]);
+
+    let mut devices = Devices::empty();
+    devices.register::<External>();
+    devices.use_transport(Box::new(transport));
+    devices.set_sysfs_root(FIXTURE);
+
+    let mut replies = Vec::new();
+    devices
+        .for_each_supported_devices(|dev| {
+            let mut data = [0x05, 0, 0, 0, 0, 0];
+            let size = dev.read_report(&mut data)?;
+            replies.push(data[..size].to_vec());
+
+            let mut data = [0x06, 0];
+            assert!(matches!(
+                dev.read_report(&mut data),
+                Err(Error::Transport { hid: 0x0001, .. })
+            ));
+            Ok(())
+        })
+        .unwrap();
+
+    assert_eq!(replies, [vec![0x05, 0x01, 0x02, 0x03]]);
+}
@@ -0,0 +1,77 @@
+use a4keyboard::color::Color;
+use a4keyboard::devices::bloody::emulator::Emulator;
+use a4keyboard::devices::bloody::emulator::EmulatorError;
+use a4keyboard::devices::Devices;
+use a4keyboard::Error;
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/sysfs");
+
+fn emulated_devices() -> (Devices, Emulator) {
+    let emulator = Emulator::new();
+
+    let mut devices = Devices::new();
+    devices.use_transport(Box::new(emulator.clone()));
+    devices.set_sysfs_root(FIXTURE);
+
+    (devices, emulator)
+}
+
+#[test]
+fn frames() {
+    let (mut devices, emulator) = emulated_devices();
+
+    let mut control = devices.gain_control().unwrap();
+    assert_eq!(emulator.hids(), [0x0005, 0x0009]);
+    assert!(emulator.keyboard(0x0005).unwrap().is_controlled());
+
+    let mut colors = [Color::default(); 104];
+    for frame in 0..10u8 {
+        for (i, color) in colors.iter_mut().enumerate() {
+            let i = i as u8;
+            *color = Color {
+                r: i.wrapping_add(frame),
+                g: i.wrapping_mul(frame),
+                b: frame,
+            };
+        }
+
+        control
+            .for_each_supported_devices(|dev| dev.set_colors(&colors))
+            .unwrap();
+    }
+
+    for hid in [0x0005, 0x0009] {
+        assert_eq!(emulator.keyboard(hid).unwrap().frame(), &colors);
+    }
+
+    drop(control);
+    assert!(!emulator.keyboard(0x0005).unwrap().is_controlled());
+    assert!(!emulator.keyboard(0x0009).unwrap().is_controlled());
+}
+
+#[test]
+fn without_control() {
+    let (mut devices, emulator) = emulated_devices();
+
+    let colors = [Color::default(); 104];
+    let result = devices.for_each_supported_devices(|dev| dev.set_colors(&colors));
+    let Err(Error::Transport { hid, source }) = result else {
+        panic!("colors are accepted without control");
+    };
+    let source = source.get_ref().unwrap().downcast_ref::<EmulatorError>();
+    assert_eq!(source, Some(&EmulatorError::NotControlled));
+    assert!(!emulator.keyboard(hid).unwrap().is_controlled());
+}
+
+#[test]
+fn release_controlled_devices() {
+    let (mut devices, emulator) = emulated_devices();
+
+    let mut control = devices.gain_control().unwrap();
+    // selectors changed after gaining control don't change released devices
+    control.set_selectors(vec!["hid:0005".parse().unwrap()]);
+    drop(control);
+
+    assert!(!emulator.keyboard(0x0005).unwrap().is_controlled());
+    assert!(!emulator.keyboard(0x0009).unwrap().is_controlled());
+}
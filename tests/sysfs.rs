@@ -0,0 +1,160 @@
+use a4keyboard::devices::selector::DeviceSelector;
+use a4keyboard::devices::transport::RecordingTransport;
+use a4keyboard::devices::Devices;
+use a4keyboard::Error;
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/sysfs");
+
+fn fixture_devices() -> (Devices, RecordingTransport) {
+    let transport = RecordingTransport::new();
+
+    let mut devices = Devices::new();
+    devices.use_transport(Box::new(transport.clone()));
+    devices.set_sysfs_root(FIXTURE);
+
+    (devices, transport)
+}
+
+fn controlled_hids(devices: &mut Devices, transport: &RecordingTransport) -> Vec<u16> {
+    devices
+        .for_each_supported_devices(|dev| dev.gain_control())
+        .unwrap();
+
+    let mut hids = Vec::from_iter(transport.take_packets().iter().map(|packet| packet.hid));
+    hids.sort();
+    hids.dedup();
+    hids
+}
+
+#[test]
+fn enumerate_fixture() {
+    let (mut devices, transport) = fixture_devices();
+
+    let mut found = Vec::new();
+    devices
+        .for_each_devices(|info| {
+            found.push((
+                info.sysfs_name().to_owned(),
+                info.name().map(ToOwned::to_owned),
+                info.uniq().map(ToOwned::to_owned),
+                devices.driver_name(info),
+            ));
+            Ok::<(), Error>(())
+        })
+        .unwrap();
+    found.sort();
+
+    assert_eq!(
+        found,
+        [
+            (
+                "0003:046D:C077.0001".to_owned(),
+                Some("Logitech USB Optical Mouse".to_owned()),
+                None,
+                None
+            ),
+            (
+                "0003:09DA:FA10.0004".to_owned(),
+                Some("A4TECH USB Device".to_owned()),
+                None,
+                None
+            ),
+            (
+                "0003:09DA:FA10.0005".to_owned(),
+                Some("A4TECH USB Device".to_owned()),
+                None,
+                Some("bloody")
+            ),
+            (
+                "0003:09DA:FA10.0008".to_owned(),
+                Some("A4TECH USB Device".to_owned()),
+                Some("8A4F0C21".to_owned()),
+                None
+            ),
+            (
+                "0003:09DA:FA10.0009".to_owned(),
+                Some("A4TECH USB Device".to_owned()),
+                Some("8A4F0C21".to_owned()),
+                Some("bloody")
+            ),
+        ]
+    );
+
+    assert_eq!(controlled_hids(&mut devices, &transport), [0x0005, 0x0009]);
+}
+
+#[test]
+fn selectors() {
+    let (mut devices, transport) = fixture_devices();
+
+    devices.set_selectors(vec![DeviceSelector::Hid(0x0005)]);
+    assert_eq!(controlled_hids(&mut devices, &transport), [0x0005]);
+
+    devices.set_selectors(vec![DeviceSelector::Uniq("8A4F0C21".to_owned())]);
+    assert_eq!(controlled_hids(&mut devices, &transport), [0x0009]);
+
+    devices.set_selectors(vec![DeviceSelector::SysfsName(
+        "0003:09da:fa10.0005".to_owned(),
+    )]);
+    assert_eq!(controlled_hids(&mut devices, &transport), [0x0005]);
+
+    devices.set_selectors(vec![
+        DeviceSelector::VidPid(0x09da, 0xfa10),
+        DeviceSelector::Hid(0x0001),
+    ]);
+    assert_eq!(controlled_hids(&mut devices, &transport), [0x0005, 0x0009]);
+
+    devices.set_selectors(vec![DeviceSelector::VidPid(0x046d, 0xc077)]);
+    assert_eq!(controlled_hids(&mut devices, &transport), []);
+    assert!(matches!(
+        devices.gain_control(),
+        Err(Error::NoSupportedDevices)
+    ));
+}
+
+#[test]
+fn empty_registry() {
+    let transport = RecordingTransport::new();
+
+    let mut devices = Devices::empty();
+    devices.use_transport(Box::new(transport.clone()));
+    devices.set_sysfs_root(FIXTURE);
+
+    assert_eq!(controlled_hids(&mut devices, &transport), []);
+}
+
+#[test]
+fn missing_root() {
+    let (mut devices, transport) = fixture_devices();
+
+    devices.set_sysfs_root(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/missing"
+    ));
+    assert!(matches!(
+        devices.for_each_supported_devices(|dev| dev.gain_control()),
+        Err(Error::Enumeration { .. })
+    ));
+    assert!(transport.take_packets().is_empty());
+}
+
+#[test]
+fn control_guard() {
+    let (mut devices, transport) = fixture_devices();
+
+    let control = devices.gain_control().unwrap();
+    assert_eq!(transport.take_packets().len(), 4);
+
+    drop(control);
+    assert_eq!(transport.take_packets().len(), 4);
+
+    devices.gain_control().unwrap().keep();
+    assert_eq!(transport.take_packets().len(), 4);
+}
+
+#[test]
+fn send_sync() {
+    fn check<T: Send + Sync>() {}
+
+    check::<Devices>();
+}
@@ -0,0 +1,58 @@
+//! Runs `a4keyboard` against virtual keyboard, requires root:
+//! `sudo -E cargo test --test uhid -- --ignored`
+
+use a4keyboard::color::Color;
+use a4keyboard::devices::bloody::protocol::Command;
+use a4keyboard::devices::testing::uhid::Report;
+use a4keyboard::devices::testing::uhid::ReportType;
+use a4keyboard::devices::testing::uhid::VirtualKeyboard;
+use std::process;
+use std::time::Duration;
+
+fn a4keyboard(keyboard: &VirtualKeyboard, args: &[&str]) {
+    let status = process::Command::new(env!("CARGO_BIN_EXE_a4keyboard"))
+        .args(["--transport", "hidraw", "--device"])
+        .arg(format!("uniq:{}", keyboard.uniq()))
+        .args(args)
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+}
+
+fn feature(command: Command) -> Report {
+    Report {
+        r#type: ReportType::Feature,
+        data: command.encode().to_vec(),
+    }
+}
+
+#[test]
+#[ignore = "requires root and uhid"]
+fn color() {
+    let keyboard = VirtualKeyboard::bloody("a4keyboard-test-color").unwrap();
+    keyboard.wait_sysfs(Duration::from_secs(5)).unwrap();
+
+    a4keyboard(&keyboard, &["color", "f00"]);
+
+    let red = Color {
+        r: 0xff,
+        g: 0,
+        b: 0,
+    };
+    let mut expected = vec![
+        feature(Command::PrepareControl),
+        feature(Command::SetControl { software: true }),
+    ];
+    expected.extend(Command::frame(&[red; 104]).map(feature));
+    assert_eq!(keyboard.take_reports(), expected);
+
+    a4keyboard(&keyboard, &["release"]);
+    assert_eq!(
+        keyboard.take_reports(),
+        [
+            feature(Command::PrepareControl),
+            feature(Command::SetControl { software: false }),
+        ]
+    );
+}